@@ -0,0 +1,48 @@
+use crate::vec3::Vec3;
+
+/// Axis-aligned bounding box
+#[derive(Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// The box with opposite corners `a` and `b`
+    pub fn new(a: Vec3, b: Vec3) -> Self {
+        Self {
+            min: Vec3(a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+            max: Vec3(a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+        }
+    }
+
+    /// The smallest box containing both boxes
+    pub fn union(self, other: Aabb) -> Self {
+        self.include(other.min).include(other.max)
+    }
+
+    /// The smallest box containing this box and `point`
+    pub fn include(self, point: Vec3) -> Self {
+        Self {
+            min: Vec3(
+                self.min.0.min(point.0),
+                self.min.1.min(point.1),
+                self.min.2.min(point.2),
+            ),
+            max: Vec3(
+                self.max.0.max(point.0),
+                self.max.1.max(point.1),
+                self.max.2.max(point.2),
+            ),
+        }
+    }
+
+    pub fn centre(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    /// Length of the diagonal between opposite corners
+    pub fn diagonal(&self) -> f32 {
+        (self.max - self.min).magnitude()
+    }
+}
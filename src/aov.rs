@@ -0,0 +1,122 @@
+use crate::hittable::HitRecord;
+use crate::integrator::Radiance;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// Arbitrary output variables: auxiliary render passes that are written
+/// alongside the beauty image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// Distance from the camera to the first hit
+    Depth,
+    /// World-space normal at the first hit
+    Normal,
+    /// Base colour of the material at the first hit
+    Albedo,
+    /// Surface coordinates of the first hit
+    Uv,
+    /// Index of the object at the first hit, -1 where nothing is hit
+    ObjectId,
+    /// Index of the material at the first hit, -1 where nothing is hit
+    MaterialId,
+    /// Light reaching the camera after exactly one bounce
+    Direct,
+    /// Light reaching the camera after two or more bounces
+    Indirect,
+    /// Light emitted by the first surface seen from the camera
+    Emission,
+    /// Sample variance of the radiance estimates within each pixel
+    Variance,
+}
+
+impl Aov {
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+            Aov::Variance => "variance",
+        }
+    }
+
+    /// Names of the channels to write, taken in order from the pass's `Vec3`s
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Uv => &["U", "V"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// Whether the pass is computed from the first intersection of the camera ray
+    pub(crate) fn needs_hit(self) -> bool {
+        !matches!(
+            self,
+            Aov::Direct | Aov::Indirect | Aov::Emission | Aov::Variance
+        )
+    }
+
+    /// Value for pixels where no sample contributed to the pass
+    pub(crate) fn empty_value(self) -> Vec3 {
+        match self {
+            Aov::Depth => Vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            Aov::ObjectId | Aov::MaterialId => Vec3(-1.0, -1.0, -1.0),
+            _ => Vec3::default(),
+        }
+    }
+
+    /// The value of the pass for a single camera sample, if it contributes
+    pub(crate) fn sample_value(self, radiance: &Radiance) -> Option<Vec3> {
+        let hit = radiance.first_hit.as_ref();
+        match self {
+            Aov::Depth => hit.map(|h| splat(h.distance)),
+            Aov::Normal => hit.map(|h| h.normal),
+            Aov::Albedo => hit.map(|h| h.albedo),
+            Aov::Uv => hit.map(|h| Vec3(h.uv.0, h.uv.1, 0.0)),
+            Aov::ObjectId => hit.map(|h| splat(h.object_id as f32)),
+            Aov::MaterialId => hit.map(|h| splat(h.material_id as f32)),
+            Aov::Direct => Some(radiance.direct),
+            Aov::Indirect => Some(radiance.indirect),
+            Aov::Emission => Some(radiance.emission),
+            Aov::Variance => Some(radiance.total()),
+        }
+    }
+}
+
+/// The first surface hit by a camera ray, as seen by the passes that describe
+/// it. Recorded by integrators so that the ray isn't traced a second time.
+#[derive(Copy, Clone)]
+pub struct FirstHit {
+    /// Distance from the camera
+    pub distance: f32,
+    pub normal: Vec3,
+    /// Base colour of the material
+    pub albedo: Vec3,
+    pub uv: (f32, f32),
+    pub object_id: usize,
+    pub material_id: usize,
+}
+
+impl FirstHit {
+    pub fn new(ray: &Ray, hit: &HitRecord) -> Self {
+        Self {
+            distance: hit.t * ray.dir.magnitude(),
+            normal: hit.normal,
+            albedo: hit.material.albedo(hit),
+            uv: hit.uv,
+            object_id: hit.object_id,
+            material_id: hit.material_id,
+        }
+    }
+}
+
+fn splat(val: f32) -> Vec3 {
+    Vec3(val, val, val)
+}
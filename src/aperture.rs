@@ -0,0 +1,155 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use image::error::{ParameterError, ParameterErrorKind};
+use image::ImageError;
+use rand::Rng;
+
+use crate::vec3::Vec3;
+
+/// Shape of the opening in a camera's lens, which gives out-of-focus
+/// highlights their shape
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApertureShape {
+    Circle,
+    /// A regular polygon formed by `blades` straight aperture blades, with a
+    /// corner at `rotation` radians anticlockwise from the right. Needs at
+    /// least 3 blades, as checked by `ApertureShape::polygon`.
+    Polygon {
+        blades: u32,
+        rotation: f32,
+    },
+    Mask(ApertureMask),
+}
+
+/// A greyscale image of the aperture, stretched over the square around the
+/// lens. Brighter pixels let through more light.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    /// Transmission of each pixel, scaled so that the brightest is 1
+    values: Vec<f32>,
+}
+
+impl ApertureMask {
+    /// `values` has one entry per pixel, row by row from the top-left corner.
+    /// Fails if there are the wrong number of values, or nothing is let
+    /// through.
+    pub fn new(width: u32, height: u32, values: Vec<f32>) -> Result<Self, ImageError> {
+        if values.len() != (width * height) as usize {
+            return Err(parameter_error(ParameterErrorKind::DimensionMismatch));
+        }
+        let max = values.iter().copied().fold(0.0, f32::max);
+        if max <= 0.0 {
+            return Err(parameter_error(ParameterErrorKind::Generic(
+                "aperture mask must let some light through".to_string(),
+            )));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            values: values.into_iter().map(|v| v.max(0.0) / max).collect(),
+        })
+    }
+
+    /// Loads the mask from an image file, using its luminance
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let image = image::open(path)?.to_luma32f();
+        let (width, height) = image.dimensions();
+        Self::new(width, height, image.into_raw())
+    }
+}
+
+fn parameter_error(kind: ParameterErrorKind) -> ImageError {
+    ImageError::Parameter(ParameterError::from_kind(kind))
+}
+
+impl ApertureShape {
+    /// A polygonal aperture, or `None` for fewer than 3 blades, which can't
+    /// enclose an opening
+    pub fn polygon(blades: u32, rotation: f32) -> Option<Self> {
+        (blades >= 3).then_some(ApertureShape::Polygon { blades, rotation })
+    }
+
+    /// A point on the aperture, within the unit disc (or the square around it
+    /// for a mask), in proportion to how much light passes through there
+    pub(crate) fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec3 {
+        match self {
+            ApertureShape::Circle => Vec3::random_in_unit_disc(rng),
+            ApertureShape::Polygon { blades, rotation } => {
+                // The polygon is made of identical triangles meeting at its
+                // centre, so pick one and then a point within it
+                let corner = rng.gen_range(0..*blades);
+                let angle = |i: u32| rotation + 2.0 * PI * i as f32 / *blades as f32;
+                let a = Vec3(angle(corner).cos(), angle(corner).sin(), 0.0);
+                let b = Vec3(angle(corner + 1).cos(), angle(corner + 1).sin(), 0.0);
+
+                let (s, t) = (rng.gen::<f32>().sqrt(), rng.gen::<f32>());
+                (s * (1.0 - t)) * a + (s * t) * b
+            }
+            ApertureShape::Mask(mask) => loop {
+                let (x, y) = (rng.gen::<f32>(), rng.gen::<f32>());
+                let column = ((x * mask.width as f32) as u32).min(mask.width - 1);
+                let row = ((y * mask.height as f32) as u32).min(mask.height - 1);
+                let value = mask.values[(row * mask.width + column) as usize];
+                if rng.gen::<f32>() < value {
+                    // Rows go downwards in the image
+                    break Vec3(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polygons_need_three_blades() {
+        assert!(ApertureShape::polygon(2, 0.0).is_none());
+        assert!(ApertureShape::polygon(3, 0.0).is_some());
+    }
+
+    #[test]
+    fn polygon_samples_stay_inside_the_polygon() {
+        let mut rng = rand::thread_rng();
+        for blades in [3, 5, 8] {
+            let rotation = 0.4;
+            let aperture = ApertureShape::polygon(blades, rotation).unwrap();
+            let corner = |i: u32| {
+                let angle = rotation + 2.0 * PI * i as f32 / blades as f32;
+                Vec3(angle.cos(), angle.sin(), 0.0)
+            };
+
+            for _ in 0..1000 {
+                let point = aperture.sample(&mut rng);
+                // Corners go anticlockwise, so the inside is left of each edge
+                for i in 0..blades {
+                    let (a, b) = (corner(i), corner(i + 1));
+                    assert!((b - a).cross(point - a).2 >= -1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mask_samples_only_where_light_gets_through() {
+        // Only the top-right quarter is open
+        let mask = ApertureMask::new(2, 2, vec![0.0, 0.5, 0.0, 0.0]).unwrap();
+        let aperture = ApertureShape::Mask(mask);
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let point = aperture.sample(&mut rng);
+            assert!(point.0 >= 0.0 && point.1 >= 0.0);
+        }
+    }
+
+    #[test]
+    fn rejects_masks_that_block_everything_or_have_the_wrong_size() {
+        assert!(ApertureMask::new(2, 2, vec![0.0; 4]).is_err());
+        assert!(ApertureMask::new(2, 2, vec![1.0; 3]).is_err());
+    }
+}
@@ -0,0 +1,483 @@
+//! Bidirectional path tracing. For each camera sample, a subpath is traced from
+//! the camera and another from a point on a light, and every prefix of one is
+//! connected to every prefix of the other. The resulting estimates are
+//! combined with multiple importance sampling, so that each path is weighted
+//! towards whichever of the strategies is best at finding it.
+
+use rand::Rng;
+
+use crate::aov::FirstHit;
+use crate::hittable::{Hittable, HittableList};
+use crate::integrator::{Radiance, RayIntegrator};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use crate::{clamp_brightness, RenderSettings, Scene, BACKGROUND_COLOR};
+
+/// Offset applied to both ends of a connection, to prevent self-intersection
+const SHADOW_EPSILON: f32 = 0.001;
+
+#[derive(Copy, Clone)]
+enum VertexKind<'a> {
+    Camera,
+    /// Starting point of a light subpath
+    Light {
+        emission: Vec3,
+        object_id: usize,
+    },
+    Surface {
+        material: &'a Material,
+        object_id: usize,
+        /// Unit vector towards the previous vertex on the subpath
+        wo: Vec3,
+    },
+}
+
+#[derive(Copy, Clone)]
+struct Vertex<'a> {
+    kind: VertexKind<'a>,
+    point: Vec3,
+    /// Zero for the camera, which isn't on a surface
+    normal: Vec3,
+    /// Throughput of the subpath up to this vertex, divided by its pdf
+    beta: Vec3,
+    /// Area density of sampling this vertex from the previous one on its subpath
+    pdf_fwd: f32,
+    /// Area density of sampling this vertex from the next one on its subpath,
+    /// as if the subpath had been traced the other way
+    pdf_rev: f32,
+    /// Whether the vertex scattered specularly
+    delta: bool,
+}
+
+impl Vertex<'_> {
+    fn is_connectible(&self) -> bool {
+        match self.kind {
+            VertexKind::Camera => false,
+            VertexKind::Light { .. } => true,
+            VertexKind::Surface { material, .. } => !material.is_specular(),
+        }
+    }
+
+    /// The object that the vertex is on, if that object emits light
+    fn light_id(&self) -> Option<usize> {
+        match self.kind {
+            VertexKind::Light { object_id, .. } => Some(object_id),
+            VertexKind::Surface {
+                material,
+                object_id,
+                ..
+            } if material.is_emissive() => Some(object_id),
+            _ => None,
+        }
+    }
+
+    /// Light emitted from this vertex towards `other`
+    fn emission_towards(&self, objects: &HittableList, other: &Vertex) -> Vec3 {
+        let emission = match self.kind {
+            VertexKind::Light { emission, .. } => emission,
+            VertexKind::Surface { material, .. } => material.emit(self.point),
+            VertexKind::Camera => return Vec3::default(),
+        };
+        let faces_other = self.normal.dot(other.point - self.point) > 0.0;
+
+        match self.light_id() {
+            Some(object_id) if faces_other || objects.emits_both_sides(object_id) => emission,
+            _ => Vec3::default(),
+        }
+    }
+
+    /// BSDF at this vertex, for light travelling between it and `next`
+    fn f(&self, next: &Vertex) -> Vec3 {
+        match self.kind {
+            VertexKind::Surface { material, wo, .. } => {
+                let wi = (next.point - self.point).normalize();
+                material.eval(self.point, self.normal, wo, wi)
+            }
+            _ => Vec3::default(),
+        }
+    }
+
+    /// Converts a density per unit solid angle at this vertex into a density
+    /// per unit area at `next`
+    fn convert_density(&self, pdf: f32, next: &Vertex) -> f32 {
+        let w = next.point - self.point;
+        let dist_sq = w.dot(w);
+        if dist_sq == 0.0 {
+            return 0.0;
+        }
+
+        match next.kind {
+            VertexKind::Camera => pdf / dist_sq,
+            _ => pdf * next.normal.dot(w / dist_sq.sqrt()).abs() / dist_sq,
+        }
+    }
+
+    /// Area density of sampling `next` from this vertex, having arrived from `prev`
+    fn pdf(&self, objects: &HittableList, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        match self.kind {
+            VertexKind::Light { .. } => self.pdf_light(objects, next),
+            VertexKind::Surface { material, .. } => {
+                let prev = prev.expect("surface vertices always have a predecessor");
+                let wp = (prev.point - self.point).normalize();
+                let wn = (next.point - self.point).normalize();
+                self.convert_density(material.pdf(self.point, self.normal, wp, wn), next)
+            }
+            VertexKind::Camera => 0.0,
+        }
+    }
+
+    /// Area density of a light subpath starting at this vertex heading to `next`
+    fn pdf_light(&self, objects: &HittableList, next: &Vertex) -> f32 {
+        let Some(object_id) = self.light_id() else {
+            return 0.0;
+        };
+
+        let cos = self.normal.dot((next.point - self.point).normalize());
+        let pdf_dir = if objects.emits_both_sides(object_id) {
+            cos.abs() / (2.0 * std::f32::consts::PI)
+        } else {
+            cos.max(0.0) / std::f32::consts::PI
+        };
+        self.convert_density(pdf_dir, next)
+    }
+
+    /// Area density of a light subpath starting at this vertex
+    fn pdf_light_origin(&self, objects: &HittableList) -> f32 {
+        self.light_id()
+            .map_or(0.0, |object_id| objects.light_pdf(object_id))
+    }
+}
+
+/// Bidirectional path tracing, connecting subpaths traced from the camera and
+/// from lights
+pub struct Bidirectional;
+
+impl RayIntegrator for Bidirectional {
+    fn radiance<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        rng: &mut R,
+    ) -> Radiance {
+        radiance(ray, &scene.objects, settings, rng)
+    }
+}
+
+/// Estimates the light arriving along a camera ray
+fn radiance<R: Rng + ?Sized>(
+    ray: &Ray,
+    objects: &HittableList,
+    settings: &RenderSettings,
+    rng: &mut R,
+) -> Radiance {
+    // A path with `depth` bounces has one more vertex, counting the camera
+    let max_vertices = settings.depth as usize + 1;
+    let (camera_path, first_hit) = camera_subpath(ray, objects, max_vertices, rng);
+    // The camera subpath always contributes at least two vertices
+    let light_path = light_subpath(objects, max_vertices.saturating_sub(2), rng);
+
+    let mut radiance = Radiance {
+        emission: BACKGROUND_COLOR,
+        first_hit,
+        ..Default::default()
+    };
+
+    for t in 2..=camera_path.len() {
+        for s in 0..=light_path.len() {
+            if s + t > max_vertices {
+                break;
+            }
+
+            let mut light = connect(objects, &light_path, &camera_path, s, t);
+            if let (Some(max), true) = (settings.indirect_clamp, s + t > 2) {
+                light = clamp_brightness(light, max);
+            }
+            match s + t {
+                2 => radiance.emission = radiance.emission + light,
+                3 => radiance.direct = radiance.direct + light,
+                _ => radiance.indirect = radiance.indirect + light,
+            }
+        }
+    }
+
+    radiance
+}
+
+/// Traces a subpath from the camera, along with the first surface it hits
+fn camera_subpath<'a, R: Rng + ?Sized>(
+    ray: &Ray,
+    objects: &'a HittableList,
+    max_vertices: usize,
+    rng: &mut R,
+) -> (Vec<Vertex<'a>>, Option<FirstHit>) {
+    let mut path = Vec::with_capacity(max_vertices);
+    path.push(Vertex {
+        kind: VertexKind::Camera,
+        point: ray.origin,
+        normal: Vec3::default(),
+        beta: Vec3(1.0, 1.0, 1.0),
+        pdf_fwd: 0.0,
+        pdf_rev: 0.0,
+        delta: false,
+    });
+
+    // The density of the camera ray is only needed for strategies that
+    // connect light subpaths directly to the camera, which aren't used
+    let first_hit = random_walk(
+        objects,
+        ray.clone(),
+        Vec3(1.0, 1.0, 1.0),
+        1.0,
+        max_vertices,
+        &mut path,
+        rng,
+    );
+    (path, first_hit)
+}
+
+fn light_subpath<'a, R: Rng + ?Sized>(
+    objects: &'a HittableList,
+    max_vertices: usize,
+    rng: &mut R,
+) -> Vec<Vertex<'a>> {
+    let mut path = Vec::with_capacity(max_vertices);
+    if max_vertices == 0 {
+        return path;
+    }
+    let Some(sample) = objects.sample_light(rng) else {
+        return path;
+    };
+
+    path.push(Vertex {
+        kind: VertexKind::Light {
+            emission: sample.emission,
+            object_id: sample.object_id,
+        },
+        point: sample.point,
+        normal: sample.normal,
+        beta: sample.emission / sample.pdf,
+        pdf_fwd: sample.pdf,
+        pdf_rev: 0.0,
+        delta: false,
+    });
+
+    let (dir, pdf_dir) = sample.sample_direction(rng);
+    if pdf_dir <= 0.0 {
+        return path;
+    }
+    let cos = sample.normal.dot(dir).abs();
+
+    let ray = Ray {
+        origin: sample.point,
+        dir,
+    };
+    let beta = (cos / (sample.pdf * pdf_dir)) * sample.emission;
+    random_walk(objects, ray, beta, pdf_dir, max_vertices, &mut path, rng);
+    path
+}
+
+/// Extends a subpath by following `ray` and repeatedly scattering it, until it
+/// escapes, is absorbed, or has `max_vertices` vertices. Returns the first
+/// surface hit along the way.
+fn random_walk<'a, R: Rng + ?Sized>(
+    objects: &'a HittableList,
+    mut ray: Ray,
+    mut beta: Vec3,
+    pdf: f32,
+    max_vertices: usize,
+    path: &mut Vec<Vertex<'a>>,
+    rng: &mut R,
+) -> Option<FirstHit> {
+    let mut pdf_fwd = pdf;
+    let mut first_hit = None;
+
+    while path.len() < max_vertices {
+        let Some(hit_record) = objects.hit(&ray, 0.001..f32::MAX) else {
+            break;
+        };
+        first_hit.get_or_insert_with(|| FirstHit::new(&ray, &hit_record));
+        let wo = -ray.dir.normalize();
+        let prev = path.len() - 1;
+
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface {
+                material: hit_record.material,
+                object_id: hit_record.object_id,
+                wo,
+            },
+            point: hit_record.intersection,
+            normal: hit_record.normal,
+            beta,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+        };
+        vertex.pdf_fwd = path[prev].convert_density(pdf_fwd, &vertex);
+        path.push(vertex);
+
+        if path.len() == max_vertices {
+            break;
+        }
+        let Some((new_ray, attenuation)) = hit_record.material.scatter(&ray, &hit_record, rng)
+        else {
+            break;
+        };
+
+        let current = path.len() - 1;
+        let wi = new_ray.dir.normalize();
+        let pdf_rev = if hit_record.material.is_specular() {
+            path[current].delta = true;
+            pdf_fwd = 0.0;
+            0.0
+        } else {
+            pdf_fwd = hit_record
+                .material
+                .pdf(hit_record.intersection, hit_record.normal, wo, wi);
+            hit_record
+                .material
+                .pdf(hit_record.intersection, hit_record.normal, wi, wo)
+        };
+
+        beta = beta * attenuation;
+        path[prev].pdf_rev = path[current].convert_density(pdf_rev, &path[prev]);
+        ray = new_ray;
+    }
+
+    first_hit
+}
+
+/// The MIS-weighted contribution of the path made from the first `s` vertices
+/// of the light subpath and the first `t` vertices of the camera subpath
+fn connect(
+    objects: &HittableList,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+) -> Vec3 {
+    let pt = &camera_path[t - 1];
+
+    let light = if s == 0 {
+        // The camera subpath has hit a light by itself
+        pt.emission_towards(objects, &camera_path[t - 2]) * pt.beta
+    } else {
+        let qs = &light_path[s - 1];
+        if !qs.is_connectible() || !pt.is_connectible() {
+            return Vec3::default();
+        }
+
+        let qs_throughput = if s == 1 {
+            pt.beta * pt.f(qs) * qs.emission_towards(objects, pt) / qs.pdf_fwd
+        } else {
+            pt.beta * pt.f(qs) * qs.f(pt) * qs.beta
+        };
+        if is_black(qs_throughput) {
+            return Vec3::default();
+        }
+        geometry_term(objects, qs, pt) * qs_throughput
+    };
+
+    if is_black(light) {
+        return Vec3::default();
+    }
+
+    mis_weight(objects, light_path, camera_path, s, t) * light
+}
+
+/// Geometric coupling between two vertices, including whether they can see
+/// each other
+fn geometry_term(objects: &HittableList, a: &Vertex, b: &Vertex) -> f32 {
+    let d = b.point - a.point;
+    let dist = d.magnitude();
+    let dir = d / dist;
+
+    let shadow_ray = Ray {
+        origin: a.point,
+        dir,
+    };
+    if objects
+        .hit(&shadow_ray, SHADOW_EPSILON..(dist - SHADOW_EPSILON))
+        .is_some()
+    {
+        return 0.0;
+    }
+
+    a.normal.dot(dir).abs() * b.normal.dot(dir).abs() / (dist * dist)
+}
+
+/// Balance heuristic weight for the strategy that uses `s` light vertices and
+/// `t` camera vertices, relative to every other strategy that could have
+/// produced the same path
+fn mis_weight(
+    objects: &HittableList,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+) -> f32 {
+    if s + t == 2 {
+        return 1.0;
+    }
+
+    let mut light = light_path[..s].to_vec();
+    let mut camera = camera_path[..t].to_vec();
+
+    // The vertices either side of the connection now have different
+    // neighbours, so their reverse densities change
+    let pt_pdf_rev = if s > 0 {
+        let qs_minus = if s > 1 { Some(&light[s - 2]) } else { None };
+        light[s - 1].pdf(objects, qs_minus, &camera[t - 1])
+    } else {
+        camera[t - 1].pdf_light_origin(objects)
+    };
+    let pt_minus_pdf_rev = if s > 0 {
+        camera[t - 1].pdf(objects, Some(&light[s - 1]), &camera[t - 2])
+    } else {
+        camera[t - 1].pdf_light(objects, &camera[t - 2])
+    };
+    if s > 0 {
+        let qs_pdf_rev = camera[t - 1].pdf(objects, Some(&camera[t - 2]), &light[s - 1]);
+        if s > 1 {
+            let qs_minus_pdf_rev = light[s - 1].pdf(objects, Some(&camera[t - 1]), &light[s - 2]);
+            light[s - 2].pdf_rev = qs_minus_pdf_rev;
+        }
+        light[s - 1].pdf_rev = qs_pdf_rev;
+        light[s - 1].delta = false;
+    }
+    camera[t - 1].pdf_rev = pt_pdf_rev;
+    camera[t - 2].pdf_rev = pt_minus_pdf_rev;
+    camera[t - 1].delta = false;
+
+    // Specular vertices have no density, and can't be connected to, so they
+    // are skipped over
+    let remap = |pdf: f32| if pdf == 0.0 { 1.0 } else { pdf };
+    let mut sum_ri = 0.0;
+
+    // Strategies with fewer camera vertices. Connecting directly to the camera
+    // isn't supported, so at least two are always needed.
+    let mut ri = 1.0;
+    for i in (2..t).rev() {
+        ri *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+        if !camera[i].delta && !camera[i - 1].delta {
+            sum_ri += ri;
+        }
+    }
+
+    // Strategies with fewer light vertices
+    let mut ri = 1.0;
+    for i in (0..s).rev() {
+        ri *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+        let prev_delta = i > 0 && light[i - 1].delta;
+        if !light[i].delta && !prev_delta {
+            sum_ri += ri;
+        }
+    }
+
+    1.0 / (1.0 + sum_ri)
+}
+
+fn is_black(col: Vec3) -> bool {
+    col.0 == 0.0 && col.1 == 0.0 && col.2 == 0.0
+}
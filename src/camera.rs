@@ -1,3 +1,11 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use crate::aabb::Aabb;
+use crate::aperture::ApertureShape;
+use crate::hittable::Hittable;
+use crate::lens::{FocusedLens, LensSystem};
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 
@@ -7,10 +15,135 @@ pub struct Camera {
     vertical: Vec3,
     origin: Vec3,
     lens_radius: f32,
+    aperture: ApertureShape,
+    /// How far the lens barrel is offset from the aperture at the corners of
+    /// the image, relative to the lens radius
+    cats_eye: f32,
+    focus_distance: f32,
+    /// Scale applied to the light reaching the sensor
+    exposure: f32,
+    projection: Projection,
+    stereo: Option<Stereo>,
 
     // Unit vectors on the focal plane
     u: Vec3,
     v: Vec3,
+    /// Unit vector pointing backwards, away from what the camera looks at
+    w: Vec3,
+}
+
+enum Projection {
+    /// Rays spread out from the lens, so that further objects look smaller
+    Perspective,
+    /// Rays are parallel, so that objects keep their size at any distance
+    Orthographic,
+    /// Longitude varies across the image and latitude up it, covering every
+    /// direction
+    Equirectangular,
+    /// Directions within `fov / 2` of the view direction, mapped to a circle
+    /// that fills the shorter side of the image
+    Fisheye {
+        fov: f32,
+        mapping: FisheyeMapping,
+        aspect_ratio: f32,
+    },
+    /// Six square faces looking right, left, up, down, backwards and forwards,
+    /// laid out in a 3x2 grid in that order
+    Cubemap,
+    /// Rays are traced from the film through each surface of a lens, which is
+    /// `scale` scene units per millimetre
+    Realistic { lens: FocusedLens, scale: f32 },
+}
+
+/// How a fisheye lens maps the angle from the view direction to the distance
+/// from the centre of the image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance is proportional to the angle
+    Equidistant,
+    /// Equal solid angles cover equal areas of the image
+    Equisolid,
+}
+
+/// Renders a separate image for each eye, packed into a single frame
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stereo {
+    /// Distance between the eyes
+    pub eye_separation: f32,
+    /// Distance at which the two views line up, so that objects there appear
+    /// at the depth of the screen. Only used by perspective cameras, whose
+    /// eyes look along parallel axes with their views shifted off-axis to
+    /// meet at this distance, and orthographic ones, whose rays are sheared
+    /// sideways to cross the other eye's at this distance.
+    pub convergence_distance: f32,
+    pub layout: StereoLayout,
+}
+
+/// Where each eye's image goes in the frame
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye on the left half, right eye on the right half
+    SideBySide,
+    /// Left eye on the top half, right eye on the bottom half
+    TopBottom,
+}
+
+impl StereoLayout {
+    /// Position within an eye's own image of `x` and `y` in the frame, each in
+    /// [0, 1] measured from the bottom-left corner, and whether it's the left
+    /// eye's
+    pub fn position_in_eye(self, x: f32, y: f32) -> (f32, f32, bool) {
+        match self {
+            StereoLayout::SideBySide if x < 0.5 => (2.0 * x, y, true),
+            StereoLayout::SideBySide => (2.0 * x - 1.0, y, false),
+            StereoLayout::TopBottom if y >= 0.5 => (x, 2.0 * y - 1.0, true),
+            StereoLayout::TopBottom => (x, 2.0 * y, false),
+        }
+    }
+}
+
+/// Settings of a real camera, in the units printed on it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PhysicalCamera {
+    /// Ratio of the focal length to the diameter of the aperture
+    pub f_number: f32,
+    /// In millimetres
+    pub focal_length: f32,
+    /// In millimetres
+    pub sensor_width: f32,
+    /// Exposure time in seconds
+    pub shutter_speed: f32,
+    /// Sensitivity of the sensor
+    pub iso: f32,
+    /// Length of one scene unit in metres
+    pub metres_per_unit: f32,
+}
+
+impl Default for PhysicalCamera {
+    /// A full-frame camera with a 50mm lens at f/2.8, 1/125s and ISO 100
+    fn default() -> Self {
+        Self {
+            f_number: 2.8,
+            focal_length: 50.0,
+            sensor_width: 36.0,
+            shutter_speed: 1.0 / 125.0,
+            iso: 100.0,
+            metres_per_unit: 1.0,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    /// Exposure value at ISO 100 for the aperture and shutter speed
+    pub fn ev100(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter_speed * 100.0 / self.iso).log2()
+    }
+
+    /// Scale from scene luminance in cd/m² to pixel values, such that the
+    /// brightest luminance the sensor can record without saturating maps to 1
+    pub fn exposure(&self) -> f32 {
+        1.0 / (1.2 * 2.0_f32.powf(self.ev100()))
+    }
 }
 
 impl Camera {
@@ -23,16 +156,7 @@ impl Camera {
         aperture: f32,
         focal_dist: f32,
     ) -> Self {
-        let look_dir_opposite = look_from - look_at;
-
-        // The screen will be a plane orthogonal to the direction we are looking
-        // in, with the point we are looking from as the origin. `-w` is the unit
-        // vector in the direction we are looking, and `u`, `v` are the pair of
-        // unit vectors that define the horizontal and vertical directions in the
-        // "looking plane" respectively.
-        let w = look_dir_opposite.normalize();
-        let u = upward.cross(w).normalize();
-        let v = w.cross(u);
+        let (u, v, w) = basis(look_from, look_at, upward);
 
         let half_height = (vfov / 2.0).tan();
         let half_width = aspect_ratio * half_height;
@@ -43,8 +167,173 @@ impl Camera {
             vertical: 2.0 * focal_dist * half_height * v,
             origin: look_from,
             lens_radius: aperture / 2.0,
+            aperture: ApertureShape::Circle,
+            cats_eye: 0.0,
+            exposure: 1.0,
+            focus_distance: focal_dist,
+            projection: Projection::Perspective,
+            stereo: None,
+            u,
+            v,
+            w,
+        }
+    }
+
+    /// A perspective camera whose field of view, depth of field and brightness
+    /// follow from the settings of a real camera. Light in the scene is taken
+    /// to be in cd/m².
+    pub fn physical(
+        look_from: Vec3,
+        look_at: Vec3,
+        upward: Vec3,
+        aspect_ratio: f32,
+        focal_dist: f32,
+        physical: &PhysicalCamera,
+    ) -> Self {
+        let sensor_height = physical.sensor_width / aspect_ratio;
+        let vfov = 2.0 * (sensor_height / (2.0 * physical.focal_length)).atan();
+        let aperture_mm = physical.focal_length / physical.f_number;
+        let aperture = aperture_mm / 1000.0 / physical.metres_per_unit;
+
+        Camera {
+            exposure: physical.exposure(),
+            ..Camera::new(
+                look_from,
+                look_at,
+                upward,
+                vfov,
+                aspect_ratio,
+                aperture,
+                focal_dist,
+            )
+        }
+    }
+
+    /// A camera whose rays all travel in the direction it is looking, starting
+    /// from a `view_width` wide rectangle centred on `look_from`. Everything is
+    /// in focus.
+    pub fn orthographic(
+        look_from: Vec3,
+        look_at: Vec3,
+        upward: Vec3,
+        view_width: f32,
+        aspect_ratio: f32,
+    ) -> Self {
+        let (u, v, w) = basis(look_from, look_at, upward);
+
+        let half_width = view_width / 2.0;
+        let half_height = half_width / aspect_ratio;
+
+        Camera {
+            bottom_left: look_from - half_width * u - half_height * v,
+            horizontal: 2.0 * half_width * u,
+            vertical: 2.0 * half_height * v,
+            origin: look_from,
+            lens_radius: 0.0,
+            aperture: ApertureShape::Circle,
+            cats_eye: 0.0,
+            exposure: 1.0,
+            focus_distance: (look_at - look_from).magnitude(),
+            projection: Projection::Orthographic,
+            stereo: None,
             u,
             v,
+            w,
+        }
+    }
+
+    /// A camera at `look_from` that sees in every direction, for 360° renders.
+    /// Best with an aspect ratio of 2:1.
+    pub fn equirectangular(look_from: Vec3, look_at: Vec3, upward: Vec3) -> Self {
+        Self::panoramic(look_from, look_at, upward, Projection::Equirectangular)
+    }
+
+    /// A camera with a circular image covering a field of view of `fov`
+    /// radians, which can exceed 180°. Pixels outside the circle are left
+    /// black.
+    pub fn fisheye(
+        look_from: Vec3,
+        look_at: Vec3,
+        upward: Vec3,
+        fov: f32,
+        mapping: FisheyeMapping,
+        aspect_ratio: f32,
+    ) -> Self {
+        let projection = Projection::Fisheye {
+            fov,
+            mapping,
+            aspect_ratio,
+        };
+        Self::panoramic(look_from, look_at, upward, projection)
+    }
+
+    /// A camera that traces rays through the surfaces of `lens` from a film of
+    /// `film_size` millimetres, with the front of the lens at `look_from`.
+    /// Moving the film to focus `focal_dist` away changes the field of view, as
+    /// with a real lens. Brightness is scaled so that the centre of the image
+    /// matches a pinhole camera, leaving the corners darker where the lens
+    /// blocks light. The lens's own surfaces limit the light it lets through,
+    /// so `with_aperture` and `with_cats_eye` have no effect.
+    pub fn realistic(
+        look_from: Vec3,
+        look_at: Vec3,
+        upward: Vec3,
+        focal_dist: f32,
+        lens: LensSystem,
+        film_size: (f32, f32),
+        metres_per_unit: f32,
+    ) -> Self {
+        let (u, v, w) = basis(look_from, look_at, upward);
+
+        let scale = 1.0 / 1000.0 / metres_per_unit;
+        let (film_width, film_height) = film_size;
+        let lens = FocusedLens::new(lens, focal_dist / scale, film_width, film_height);
+
+        Camera {
+            bottom_left: Vec3::default(),
+            horizontal: Vec3::default(),
+            vertical: Vec3::default(),
+            origin: look_from,
+            lens_radius: 0.0,
+            aperture: ApertureShape::Circle,
+            cats_eye: 0.0,
+            exposure: lens_exposure(&lens),
+            focus_distance: focal_dist,
+            projection: Projection::Realistic { lens, scale },
+            stereo: None,
+            u,
+            v,
+            w,
+        }
+    }
+
+    /// A camera that renders the six faces of a cube map, for baking
+    /// environment maps. Faces are aligned with the camera, and best with an
+    /// aspect ratio of 3:2.
+    pub fn cubemap(look_from: Vec3, look_at: Vec3, upward: Vec3) -> Self {
+        Self::panoramic(look_from, look_at, upward, Projection::Cubemap)
+    }
+
+    /// A pinhole camera that picks the direction of each ray from the image
+    /// position alone
+    fn panoramic(look_from: Vec3, look_at: Vec3, upward: Vec3, projection: Projection) -> Self {
+        let (u, v, w) = basis(look_from, look_at, upward);
+
+        Camera {
+            bottom_left: Vec3::default(),
+            horizontal: Vec3::default(),
+            vertical: Vec3::default(),
+            origin: look_from,
+            lens_radius: 0.0,
+            aperture: ApertureShape::Circle,
+            cats_eye: 0.0,
+            exposure: 1.0,
+            focus_distance: (look_at - look_from).magnitude(),
+            projection,
+            stereo: None,
+            u,
+            v,
+            w,
         }
     }
 
@@ -52,15 +341,382 @@ impl Camera {
         self.bottom_left + x * self.horizontal + y * self.vertical
     }
 
-    pub fn get_ray(&self, x: f32, y: f32) -> Ray {
-        let random_in_lens = self.lens_radius * Vec3::random_in_unit_disc();
-        let offset = random_in_lens.0 * self.u + random_in_lens.1 * self.v;
-        let ray_origin = self.origin + offset;
+    /// Distance from the camera to the plane that is in focus
+    pub(crate) fn focus_distance(&self) -> f32 {
+        self.focus_distance
+    }
+
+    /// Scale applied to the light reaching the sensor, which is 1 unless the
+    /// camera is physical or realistic
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Moves the plane in focus to `distance` from the camera, keeping the
+    /// field of view the same unless the camera is realistic
+    pub fn with_focus_distance(self, distance: f32) -> Self {
+        match self.projection {
+            Projection::Perspective => {
+                let scale = distance / self.focus_distance;
+                Camera {
+                    bottom_left: self.origin + scale * (self.bottom_left - self.origin),
+                    horizontal: scale * self.horizontal,
+                    vertical: scale * self.vertical,
+                    focus_distance: distance,
+                    ..self
+                }
+            }
+            Projection::Realistic { ref lens, scale } => {
+                let lens = lens.refocus(distance / scale);
+                Camera {
+                    exposure: lens_exposure(&lens),
+                    focus_distance: distance,
+                    projection: Projection::Realistic { lens, scale },
+                    ..self
+                }
+            }
+            _ => Camera {
+                focus_distance: distance,
+                ..self
+            },
+        }
+    }
+
+    /// Focuses on `point`, measuring its distance along the view direction
+    pub fn focus_on(self, point: Vec3) -> Self {
+        let distance = -(point - self.origin).dot(self.w);
+        if distance > 0.0 {
+            self.with_focus_distance(distance)
+        } else {
+            self
+        }
+    }
+
+    /// Focuses on whatever in `objects` is seen through the centre of the lens
+    /// at `x` and `y`, each in [0, 1] measured from the bottom-left corner of
+    /// a single eye's image with stereo. Keeps the current focus if nothing is
+    /// there.
+    pub fn autofocus(self, objects: &impl Hittable, x: f32, y: f32) -> Self {
+        let hit = self
+            .central_ray(x, y)
+            .and_then(|ray| objects.hit(&ray, 0.001..f32::MAX));
+        match hit {
+            Some(hit) => self.focus_on(hit.intersection),
+            None => self,
+        }
+    }
+
+    /// The ray through the image at `x` and `y` from the centre of the lens,
+    /// ignoring stereo
+    fn central_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        match self.projection {
+            Projection::Perspective => Some(Ray {
+                origin: self.origin,
+                dir: self.point_on_focal_plane(x, y) - self.origin,
+            }),
+            Projection::Orthographic => Some(Ray {
+                origin: self.point_on_focal_plane(x, y),
+                dir: -self.w,
+            }),
+            Projection::Realistic { ref lens, scale } => lens
+                .central_ray(x, y)
+                .map(|ray| self.lens_ray_to_world(&ray, lens, scale)),
+            _ => {
+                let dir = self.panoramic_direction(x, y)?;
+                Some(Ray {
+                    origin: self.origin,
+                    dir: dir.0 * self.u + dir.1 * self.v + dir.2 * self.w,
+                })
+            }
+        }
+    }
 
-        // TODO: Normalise the ray direction?
+    /// Converts a ray leaving a realistic camera's lens to world space
+    fn lens_ray_to_world(&self, ray: &Ray, lens: &FocusedLens, scale: f32) -> Ray {
+        // Lens space has the scene towards +z, with the front of the lens at
+        // the camera's position
+        let origin = scale * (ray.origin - Vec3(0.0, 0.0, lens.front()));
         Ray {
-            origin: ray_origin,
-            dir: self.point_on_focal_plane(x, y) - ray_origin,
+            origin: self.origin + origin.0 * self.u + origin.1 * self.v - origin.2 * self.w,
+            dir: ray.dir.0 * self.u + ray.dir.1 * self.v - ray.dir.2 * self.w,
+        }
+    }
+
+    /// Renders both eyes into the frame. The camera should be set up with the
+    /// aspect ratio of a single eye's image. With an equirectangular projection
+    /// this gives an omni-directional stereo panorama, where the eyes move
+    /// around a circle as they turn to face each direction.
+    pub fn with_stereo(self, stereo: Stereo) -> Self {
+        Camera {
+            stereo: Some(stereo),
+            ..self
         }
     }
+
+    /// Gives out-of-focus highlights the shape of `aperture` instead of a circle
+    pub fn with_aperture(self, aperture: ApertureShape) -> Self {
+        Camera { aperture, ..self }
+    }
+
+    /// Scales the light reaching the sensor by `exposure`, such as a physical
+    /// camera's, whatever the projection
+    pub fn with_exposure(self, exposure: f32) -> Self {
+        Camera { exposure, ..self }
+    }
+
+    /// Clips the aperture with the lens barrel towards the edges of the image,
+    /// which squashes out-of-focus highlights into a cat's eye shape and
+    /// darkens the corners. At a `strength` of 1, the barrel is offset by the
+    /// lens radius at the corners.
+    pub fn with_cats_eye(self, strength: f32) -> Self {
+        Camera {
+            cats_eye: strength,
+            ..self
+        }
+    }
+
+    /// The ray through the image at `x` and `y`, each in [0, 1] measured from
+    /// the bottom-left corner. `None` where the camera doesn't see anything.
+    pub fn get_ray<R: Rng + ?Sized>(&self, x: f32, y: f32, rng: &mut R) -> Option<Ray> {
+        let Some(stereo) = self.stereo else {
+            return self.eye_ray(x, y, 0.0, self.focus_distance, rng);
+        };
+
+        let (x, y, left) = stereo.layout.position_in_eye(x, y);
+        let side = if left { -1.0 } else { 1.0 };
+        let eye = side * stereo.eye_separation / 2.0;
+        self.eye_ray(x, y, eye, stereo.convergence_distance, rng)
+    }
+
+    /// The ray for an eye `eye` to the right of the camera's position
+    fn eye_ray<R: Rng + ?Sized>(
+        &self,
+        x: f32,
+        y: f32,
+        eye: f32,
+        convergence_distance: f32,
+        rng: &mut R,
+    ) -> Option<Ray> {
+        let eye_origin = self.origin + eye * self.u;
+
+        match self.projection {
+            Projection::Perspective => {
+                // The eye's pinhole ray passes through the point seen by the
+                // centre of the camera at the convergence distance. The lens
+                // focuses where that ray crosses the focal plane.
+                let towards_focal_plane = self.point_on_focal_plane(x, y) - self.origin;
+                let converged = self.origin
+                    + (convergence_distance / self.focus_distance) * towards_focal_plane;
+                let focused = eye_origin
+                    + (self.focus_distance / convergence_distance) * (converged - eye_origin);
+
+                let point_in_lens = self.aperture.sample(rng);
+                if self.cats_eye > 0.0 {
+                    // Measured from the centre of the image, so that the
+                    // corners are a distance of 1 away
+                    let aspect_ratio = self.horizontal.magnitude() / self.vertical.magnitude();
+                    let from_centre = Vec3((2.0 * x - 1.0) * aspect_ratio, 2.0 * y - 1.0, 0.0)
+                        / (aspect_ratio * aspect_ratio + 1.0).sqrt();
+                    let barrel_offset = point_in_lens - self.cats_eye * from_centre;
+                    if barrel_offset.dot(barrel_offset) > 1.0 {
+                        return None;
+                    }
+                }
+                let random_in_lens = self.lens_radius * point_in_lens;
+                let offset = random_in_lens.0 * self.u + random_in_lens.1 * self.v;
+                let ray_origin = eye_origin + offset;
+
+                // TODO: Normalise the ray direction?
+                Some(Ray {
+                    origin: ray_origin,
+                    dir: focused - ray_origin,
+                })
+            }
+            // Shifting the rays alone would give both eyes the same image, so
+            // they are sheared to give nearer and further objects disparity
+            Projection::Orthographic => Some(Ray {
+                origin: self.point_on_focal_plane(x, y) + eye * self.u,
+                dir: -self.w - (eye / convergence_distance) * self.u,
+            }),
+            Projection::Realistic { ref lens, scale } => {
+                let ray = self.lens_ray_to_world(&lens.sample_ray(x, y, rng)?, lens, scale);
+                Some(Ray {
+                    origin: ray.origin + eye * self.u,
+                    dir: ray.dir,
+                })
+            }
+            _ => {
+                let dir = self.panoramic_direction(x, y)?;
+                let origin = match self.projection {
+                    // The eyes sit on a circle, perpendicular to the direction
+                    // they're facing
+                    Projection::Equirectangular => {
+                        let horizontal = (dir.0 * dir.0 + dir.2 * dir.2).sqrt();
+                        let (side_x, side_z) = if horizontal > 0.0 {
+                            (-dir.2 / horizontal, dir.0 / horizontal)
+                        } else {
+                            (1.0, 0.0)
+                        };
+                        self.origin + eye * (side_x * self.u + side_z * self.w)
+                    }
+                    _ => eye_origin,
+                };
+                Some(Ray {
+                    origin,
+                    dir: dir.0 * self.u + dir.1 * self.v + dir.2 * self.w,
+                })
+            }
+        }
+    }
+
+    /// Direction of the ray through the image at `x` and `y` for panoramic
+    /// projections, relative to the camera looking along -z
+    fn panoramic_direction(&self, x: f32, y: f32) -> Option<Vec3> {
+        match self.projection {
+            Projection::Equirectangular => {
+                let longitude = (x - 0.5) * 2.0 * PI;
+                let latitude = (y - 0.5) * PI;
+                Some(Vec3(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                ))
+            }
+            Projection::Fisheye {
+                fov,
+                mapping,
+                aspect_ratio,
+            } => {
+                // Centred on the image, with the circle having a radius of 1
+                let (px, py) = if aspect_ratio >= 1.0 {
+                    ((2.0 * x - 1.0) * aspect_ratio, 2.0 * y - 1.0)
+                } else {
+                    (2.0 * x - 1.0, (2.0 * y - 1.0) / aspect_ratio)
+                };
+                let r = (px * px + py * py).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+
+                let max_theta = fov / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max_theta,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (max_theta / 2.0).sin()).asin(),
+                };
+                let phi = py.atan2(px);
+                Some(Vec3(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                ))
+            }
+            Projection::Cubemap => {
+                let column = ((x * 3.0) as usize).min(2);
+                let row = (((1.0 - y) * 2.0) as usize).min(1);
+                // Position on the face, with `t` pointing downwards
+                let s = 2.0 * (x * 3.0 - column as f32) - 1.0;
+                let t = 2.0 * ((1.0 - y) * 2.0 - row as f32) - 1.0;
+
+                // Follows the OpenGL convention for the orientation of each face
+                let dir = match row * 3 + column {
+                    0 => Vec3(1.0, -t, -s),
+                    1 => Vec3(-1.0, -t, s),
+                    2 => Vec3(s, 1.0, t),
+                    3 => Vec3(s, -1.0, -t),
+                    4 => Vec3(s, -t, 1.0),
+                    _ => Vec3(-s, -t, -1.0),
+                };
+                Some(dir.normalize())
+            }
+            Projection::Perspective | Projection::Orthographic | Projection::Realistic { .. } => {
+                None
+            }
+        }
+    }
+}
+
+/// Where a perspective camera looking along `direction` needs to be for the
+/// whole of `bounds` to fit in its view, with `margin` extra space around it
+/// as a fraction of the box's size. Returns the points to look from and at,
+/// or `None` if `direction` is zero or parallel to `upward`, which leaves the
+/// camera with no way to tell which way is up.
+pub fn frame_bounds(
+    bounds: &Aabb,
+    direction: Vec3,
+    upward: Vec3,
+    vfov: f32,
+    aspect_ratio: f32,
+    margin: f32,
+) -> Option<(Vec3, Vec3)> {
+    let length = direction.magnitude();
+    if length == 0.0 || upward.cross(direction).magnitude() <= 1e-6 * length * upward.magnitude() {
+        return None;
+    }
+
+    // Fits the sphere around the box within the narrower of the horizontal and
+    // vertical fields of view
+    let radius = (1.0 + margin) * bounds.diagonal() / 2.0;
+    let half_vfov = vfov / 2.0;
+    let half_hfov = (aspect_ratio * half_vfov.tan()).atan();
+    let distance = radius / half_vfov.min(half_hfov).sin();
+
+    let look_at = bounds.centre();
+    Some((look_at - distance * direction / length, look_at))
+}
+
+/// The orthonormal basis of a camera at `look_from`: `u` points right, `v` up
+/// and `w` backwards
+fn basis(look_from: Vec3, look_at: Vec3, upward: Vec3) -> (Vec3, Vec3, Vec3) {
+    let look_dir_opposite = look_from - look_at;
+
+    // The screen will be a plane orthogonal to the direction we are looking
+    // in, with the point we are looking from as the origin. `-w` is the unit
+    // vector in the direction we are looking, and `u`, `v` are the pair of
+    // unit vectors that define the horizontal and vertical directions in the
+    // "looking plane" respectively.
+    let w = look_dir_opposite.normalize();
+    let u = upward.cross(w).normalize();
+    let v = w.cross(u);
+
+    (u, v, w)
+}
+
+/// Exposure that makes up for the light a realistic camera's lens blocks at
+/// the centre of the image
+fn lens_exposure(lens: &FocusedLens) -> f32 {
+    let transmission = lens.transmission_at_centre();
+    if transmission > 0.0 {
+        1.0 / transmission
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orthographic_stereo_rays_cross_at_the_convergence_distance() {
+        let camera = Camera::orthographic(
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(0.0, 0.0, -1.0),
+            Vec3(0.0, 1.0, 0.0),
+            2.0,
+            1.0,
+        )
+        .with_stereo(Stereo {
+            eye_separation: 0.2,
+            convergence_distance: 5.0,
+            layout: StereoLayout::SideBySide,
+        });
+        let mut rng = rand::thread_rng();
+        let left = camera.get_ray(0.3, 0.6, &mut rng).unwrap();
+        let right = camera.get_ray(0.8, 0.6, &mut rng).unwrap();
+
+        // Both directions are one unit along the view direction
+        let crossing = |ray: &Ray| ray.origin + 5.0 * ray.dir;
+        assert!((crossing(&left) - crossing(&right)).magnitude() < 1e-5);
+        assert!((left.origin - right.origin).magnitude() > 0.19);
+    }
 }
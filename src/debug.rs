@@ -0,0 +1,174 @@
+//! Integrators that visualise properties of the scene rather than simulating
+//! light, for inspecting geometry and materials. Each writes its value to the
+//! emission of the returned `Radiance`.
+
+use rand::Rng;
+
+use crate::aov::FirstHit;
+use crate::hittable::{HitRecord, Hittable};
+use crate::integrator::{trace_path, Radiance, RayIntegrator};
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use crate::{RenderSettings, Scene};
+
+/// Surface normals at the first hit, mapped from [-1, 1] to [0, 1]
+pub struct Normals;
+
+impl RayIntegrator for Normals {
+    fn radiance<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _: &RenderSettings,
+        _: &mut R,
+    ) -> Radiance {
+        first_hit(ray, scene, |hit| 0.5 * (hit.normal.normalize() + 1.0))
+    }
+}
+
+/// Distance to the first hit, as a brightness that fades from white at the
+/// camera to black at `max_distance`
+pub struct Depth {
+    /// Twice the camera's focus distance if not given
+    pub max_distance: Option<f32>,
+}
+
+impl RayIntegrator for Depth {
+    fn radiance<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _: &RenderSettings,
+        _: &mut R,
+    ) -> Radiance {
+        let max_distance = self
+            .max_distance
+            .unwrap_or_else(|| 2.0 * scene.camera.focus_distance());
+        first_hit(ray, scene, |hit| {
+            let distance = hit.t * ray.dir.magnitude();
+            let brightness = (1.0 - distance / max_distance).max(0.0);
+            Vec3(brightness, brightness, brightness)
+        })
+    }
+}
+
+/// Base colour of the surface at the first hit
+pub struct Albedo;
+
+impl RayIntegrator for Albedo {
+    fn radiance<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _: &RenderSettings,
+        _: &mut R,
+    ) -> Radiance {
+        first_hit(ray, scene, |hit| hit.material.albedo(hit))
+    }
+}
+
+/// Fraction of the hemisphere above the first hit that isn't blocked by other
+/// geometry, weighted by the cosine of the angle to the normal, as a greyscale
+/// value. Materials are ignored, so lights occlude like any other surface.
+/// Empty space is white.
+pub struct AmbientOcclusion {
+    /// Cosine-weighted rays traced from each hit
+    pub samples: u32,
+    /// Geometry further away than this doesn't occlude. Unlimited if not
+    /// given.
+    pub max_distance: Option<f32>,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            samples: 16,
+            max_distance: None,
+        }
+    }
+}
+
+impl RayIntegrator for AmbientOcclusion {
+    fn radiance<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _: &RenderSettings,
+        rng: &mut R,
+    ) -> Radiance {
+        let Some(hit) = scene.objects.hit(ray, 0.001..f32::MAX) else {
+            return Radiance {
+                emission: Vec3(1.0, 1.0, 1.0),
+                ..Default::default()
+            };
+        };
+
+        let normal = if hit.normal.dot(ray.dir) > 0.0 {
+            -hit.normal
+        } else {
+            hit.normal
+        };
+        let max_distance = self.max_distance.unwrap_or(f32::MAX);
+        let samples = self.samples.max(1);
+
+        let unoccluded = (0..samples)
+            .filter(|_| {
+                // Offsetting the normal by a point on the unit sphere gives a
+                // cosine-weighted direction
+                let dir = normal + Vec3::random_unit_vector(rng);
+                if dir.dot(dir) < 1e-8 {
+                    return true;
+                }
+                let occlusion_ray = Ray {
+                    origin: hit.intersection,
+                    dir: dir.normalize(),
+                };
+                scene
+                    .objects
+                    .hit(&occlusion_ray, 0.001..max_distance)
+                    .is_none()
+            })
+            .count();
+        let visibility = unoccluded as f32 / samples as f32;
+
+        Radiance {
+            emission: Vec3(visibility, visibility, visibility),
+            first_hit: Some(FirstHit::new(ray, &hit)),
+            ..Default::default()
+        }
+    }
+}
+
+/// Number of times each path scatters with the path tracer, from black for
+/// none to white for `depth`
+pub struct BounceCount;
+
+impl RayIntegrator for BounceCount {
+    fn radiance<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        rng: &mut R,
+    ) -> Radiance {
+        let (radiance, bounces) = trace_path(ray, scene, settings, rng);
+        let brightness = bounces as f32 / settings.depth.max(1) as f32;
+        Radiance {
+            emission: Vec3(brightness, brightness, brightness),
+            first_hit: radiance.first_hit,
+            ..Default::default()
+        }
+    }
+}
+
+/// Shades the first surface hit by the ray, leaving empty space black
+fn first_hit(ray: &Ray, scene: &Scene, shade: impl Fn(&HitRecord) -> Vec3) -> Radiance {
+    match scene.objects.hit(ray, 0.001..f32::MAX) {
+        Some(hit) => Radiance {
+            emission: shade(&hit),
+            first_hit: Some(FirstHit::new(ray, &hit)),
+            ..Default::default()
+        },
+        None => Radiance::default(),
+    }
+}
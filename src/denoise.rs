@@ -0,0 +1,156 @@
+use rayon::prelude::*;
+
+use crate::vec3::Vec3;
+
+/// B3-spline kernel used by each pass of the à-trous wavelet transform
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Feature buffers gathered at the first hit of each pixel, used to stop the
+/// denoiser from blurring across edges. Each has one entry per pixel.
+pub struct DenoiseGuides<'a> {
+    pub albedo: &'a [Vec3],
+    pub normal: &'a [Vec3],
+    /// Distance to the first hit, infinite where nothing is hit
+    pub depth: &'a [Vec3],
+}
+
+pub struct DenoiseSettings {
+    /// Number of wavelet passes. Each pass doubles the filter footprint.
+    pub iterations: u32,
+    /// How much neighbouring colours can differ before being ignored. Halved
+    /// after every pass.
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    pub sigma_albedo: f32,
+    /// Relative to the depth of the pixel being filtered
+    pub sigma_depth: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 1.0,
+            sigma_normal: 0.3,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+/// Denoises a rendered image using an edge-avoiding à-trous wavelet filter.
+/// The image is divided by the albedo before filtering, so that texture detail
+/// is preserved, and multiplied back afterwards.
+pub fn denoise(
+    image: &[Vec3],
+    width: u32,
+    height: u32,
+    guides: &DenoiseGuides,
+    settings: &DenoiseSettings,
+) -> Vec<Vec3> {
+    let albedo: Vec<Vec3> = guides
+        .albedo
+        .iter()
+        .map(|a| a.map(|c| c.max(0.01)))
+        .collect();
+
+    let mut illumination: Vec<Vec3> = image.iter().zip(&albedo).map(|(&c, &a)| c / a).collect();
+
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        let sigma_color = settings.sigma_color / (1 << iteration) as f32;
+        illumination = (0..(width * height) as usize)
+            .into_par_iter()
+            .map(|n| {
+                filter_pixel(
+                    &illumination,
+                    n,
+                    width,
+                    height,
+                    step,
+                    sigma_color,
+                    guides,
+                    settings,
+                )
+            })
+            .collect();
+    }
+
+    illumination
+        .into_iter()
+        .zip(albedo)
+        .map(|(c, a)| c * a)
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn filter_pixel(
+    illumination: &[Vec3],
+    n: usize,
+    width: u32,
+    height: u32,
+    step: i32,
+    sigma_color: f32,
+    guides: &DenoiseGuides,
+    settings: &DenoiseSettings,
+) -> Vec3 {
+    let x = (n as u32 % width) as i32;
+    let y = (n as u32 / width) as i32;
+
+    let color = compress(illumination[n]);
+    let normal = guides.normal[n];
+    let albedo = guides.albedo[n];
+    let depth = guides.depth[n].0;
+
+    let mut sum = Vec3::default();
+    let mut weight_sum = 0.0;
+
+    for (j, ky) in KERNEL.iter().enumerate() {
+        for (i, kx) in KERNEL.iter().enumerate() {
+            let qx = x + (i as i32 - 2) * step;
+            let qy = y + (j as i32 - 2) * step;
+            if qx < 0 || qy < 0 || qx >= width as i32 || qy >= height as i32 {
+                continue;
+            }
+            let q = (qy as u32 * width + qx as u32) as usize;
+
+            let color_dist = distance_sq(color, compress(illumination[q]));
+            let normal_dist = distance_sq(normal, guides.normal[q]);
+            let albedo_dist = distance_sq(albedo, guides.albedo[q]);
+            let depth_dist = relative_depth_difference(depth, guides.depth[q].0);
+
+            let weight = kx
+                * ky
+                * (-color_dist / (sigma_color * sigma_color)).exp()
+                * (-normal_dist / (settings.sigma_normal * settings.sigma_normal)).exp()
+                * (-albedo_dist / (settings.sigma_albedo * settings.sigma_albedo)).exp()
+                * (-(depth_dist * depth_dist) / (settings.sigma_depth * settings.sigma_depth))
+                    .exp();
+
+            sum = sum + weight * illumination[q];
+            weight_sum += weight;
+        }
+    }
+
+    // The center pixel always has a weight of at least 9/64
+    sum / weight_sum
+}
+
+/// Maps HDR values into [0, 1), so that colour differences are comparable
+/// between bright and dark parts of the image
+fn compress(col: Vec3) -> Vec3 {
+    col.map(|c| c / (1.0 + c))
+}
+
+fn distance_sq(a: Vec3, b: Vec3) -> f32 {
+    let d = a - b;
+    d.dot(d)
+}
+
+fn relative_depth_difference(p: f32, q: f32) -> f32 {
+    match (p.is_finite(), q.is_finite()) {
+        (true, true) => (p - q).abs() / p.max(1e-4),
+        (false, false) => 0.0,
+        _ => f32::INFINITY,
+    }
+}
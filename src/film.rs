@@ -0,0 +1,256 @@
+use std::ops::Range;
+
+use crate::aov::Aov;
+use crate::filter::Filter;
+use crate::vec3::Vec3;
+
+/// Accumulates filtered samples for the whole image. Pixels are stored
+/// row-major, starting from the top-left corner.
+pub(crate) struct Film {
+    width: u32,
+    height: u32,
+    filter: Filter,
+    sums: Vec<Vec3>,
+    weights: Vec<f32>,
+    aovs: Vec<AovLayer>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter, aovs: &[Aov]) -> Self {
+        let num_pixels = (width * height) as usize;
+        Self {
+            width,
+            height,
+            filter,
+            sums: vec![Vec3::default(); num_pixels],
+            weights: vec![0.0; num_pixels],
+            aovs: aovs
+                .iter()
+                .map(|&aov| AovLayer::new(aov, num_pixels))
+                .collect(),
+        }
+    }
+
+    /// Creates a tile for rendering the given rows. The tile also covers the
+    /// neighbouring rows that samples can splat into.
+    pub fn tile(&self, rows: Range<u32>) -> FilmTile {
+        let pad = self.filter.radius.ceil() as u32;
+        let stored_rows = rows.start.saturating_sub(pad)..(rows.end + pad).min(self.height);
+        let num_pixels = (stored_rows.len() as u32 * self.width) as usize;
+
+        FilmTile {
+            width: self.width,
+            rows: stored_rows,
+            filter: self.filter,
+            sums: vec![Vec3::default(); num_pixels],
+            weights: vec![0.0; num_pixels],
+            aovs: self
+                .aovs
+                .iter()
+                .map(|layer| AovLayer::new(layer.aov, num_pixels))
+                .collect(),
+        }
+    }
+
+    pub fn merge(&mut self, tile: FilmTile) {
+        let offset = (tile.rows.start * self.width) as usize;
+        for (n, (sum, weight)) in tile.sums.into_iter().zip(tile.weights).enumerate() {
+            self.sums[offset + n] = self.sums[offset + n] + sum;
+            self.weights[offset + n] += weight;
+        }
+        for (layer, tile_layer) in self.aovs.iter_mut().zip(tile.aovs) {
+            layer.merge(offset, tile_layer);
+        }
+    }
+
+    /// Normalises the accumulated samples into the final pixel values, along
+    /// with the values of each of the auxiliary passes
+    pub fn resolve(self) -> (Vec<Vec3>, Vec<(Aov, Vec<Vec3>)>) {
+        let aovs = self
+            .aovs
+            .into_iter()
+            .map(|layer| (layer.aov, layer.resolve()))
+            .collect();
+
+        let beauty = self
+            .sums
+            .into_iter()
+            .zip(self.weights)
+            .map(|(sum, weight)| {
+                if weight > 0.0 {
+                    // Filters with negative lobes can make pixels negative
+                    (sum / weight).map(|c| c.max(0.0))
+                } else {
+                    Vec3::default()
+                }
+            })
+            .collect();
+
+        (beauty, aovs)
+    }
+}
+
+pub(crate) struct FilmTile {
+    width: u32,
+    rows: Range<u32>,
+    filter: Filter,
+    sums: Vec<Vec3>,
+    weights: Vec<f32>,
+    aovs: Vec<AovLayer>,
+}
+
+impl FilmTile {
+    /// Splats a sample at raster position (x, y) onto every pixel within the
+    /// filter radius, where y is measured downwards from the top of the image.
+    pub fn add_sample(&mut self, x: f32, y: f32, value: Vec3) {
+        let r = self.filter.radius;
+        let columns = pixel_range(x, r, 0..self.width);
+        let rows = pixel_range(y, r, self.rows.clone());
+
+        for row in rows {
+            for column in columns.clone() {
+                let dx = x - (column as f32 + 0.5);
+                let dy = y - (row as f32 + 0.5);
+                let weight = self.filter.evaluate(dx, dy);
+                if weight != 0.0 {
+                    let n = ((row - self.rows.start) * self.width + column) as usize;
+                    self.sums[n] = self.sums[n] + weight * value;
+                    self.weights[n] += weight;
+                }
+            }
+        }
+    }
+
+    /// Adds a sample to the pixel at (column, row) of the given auxiliary pass.
+    /// These are not filtered, so only affect the pixel the sample is in.
+    pub fn add_aov_sample(&mut self, layer: usize, column: u32, row: u32, value: Vec3) {
+        let n = ((row - self.rows.start) * self.width + column) as usize;
+        self.aovs[layer].add_sample(n, value);
+    }
+}
+
+/// Per-pixel statistics for an auxiliary pass
+struct AovLayer {
+    aov: Aov,
+    sums: Vec<Vec3>,
+    /// Sums of squared samples, only tracked for the variance pass
+    squares: Vec<Vec3>,
+    counts: Vec<u32>,
+}
+
+impl AovLayer {
+    fn new(aov: Aov, num_pixels: usize) -> Self {
+        let squares = if aov == Aov::Variance {
+            vec![Vec3::default(); num_pixels]
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            aov,
+            sums: vec![Vec3::default(); num_pixels],
+            squares,
+            counts: vec![0; num_pixels],
+        }
+    }
+
+    fn add_sample(&mut self, n: usize, value: Vec3) {
+        match self.aov {
+            // Averaging IDs is meaningless, so keep the first one
+            Aov::ObjectId | Aov::MaterialId => {
+                if self.counts[n] == 0 {
+                    self.sums[n] = value;
+                    self.counts[n] = 1;
+                }
+            }
+            _ => {
+                self.sums[n] = self.sums[n] + value;
+                if !self.squares.is_empty() {
+                    self.squares[n] = self.squares[n] + value * value;
+                }
+                self.counts[n] += 1;
+            }
+        }
+    }
+
+    fn merge(&mut self, offset: usize, tile: AovLayer) {
+        for (n, (sum, count)) in tile.sums.into_iter().zip(tile.counts).enumerate() {
+            self.sums[offset + n] = self.sums[offset + n] + sum;
+            self.counts[offset + n] += count;
+        }
+        for (n, square) in tile.squares.into_iter().enumerate() {
+            self.squares[offset + n] = self.squares[offset + n] + square;
+        }
+    }
+
+    fn resolve(self) -> Vec<Vec3> {
+        (0..self.sums.len())
+            .map(|n| {
+                let count = self.counts[n] as f32;
+                if self.counts[n] == 0 {
+                    self.aov.empty_value()
+                } else if self.aov == Aov::Variance {
+                    if self.counts[n] < 2 {
+                        Vec3::default()
+                    } else {
+                        let mean = self.sums[n] / count;
+                        ((self.squares[n] - count * mean * mean) / (count - 1.0))
+                            .map(|v| v.max(0.0))
+                    }
+                } else {
+                    self.sums[n] / count
+                }
+            })
+            .collect()
+    }
+}
+
+/// Indices of the pixels whose centers are within `radius` of `pos`
+fn pixel_range(pos: f32, radius: f32, bounds: Range<u32>) -> Range<u32> {
+    let start = (pos - 0.5 - radius).ceil().max(bounds.start as f32) as u32;
+    let end = ((pos - 0.5 + radius).floor() + 1.0).min(bounds.end as f32) as u32;
+    start..end.max(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterKind;
+
+    #[test]
+    fn pixel_range_covers_centers_within_radius() {
+        assert_eq!(pixel_range(2.3, 0.5, 0..10), 2..3);
+        assert_eq!(pixel_range(2.3, 1.0, 0..10), 1..3);
+        assert_eq!(pixel_range(0.2, 2.0, 0..10), 0..2);
+        assert_eq!(pixel_range(9.9, 2.0, 0..10), 8..10);
+    }
+
+    #[test]
+    fn weights_are_normalised_for_every_filter() {
+        let value = Vec3(0.25, 0.5, 1.0);
+        for kind in [
+            FilterKind::Box,
+            FilterKind::Tent,
+            FilterKind::Gaussian,
+            FilterKind::Mitchell,
+            FilterKind::Lanczos,
+        ] {
+            let filter = Filter::new(kind, kind.default_radius()).unwrap();
+            let mut film = Film::new(8, 8, filter, &[]);
+            let mut tile = film.tile(0..8);
+            for n in 0..64 * 16 {
+                // Evenly spread samples, four per pixel along each axis
+                let x = (n % 32) as f32 / 4.0 + 0.125;
+                let y = (n / 32) as f32 / 4.0 + 0.125;
+                tile.add_sample(x, y, value);
+            }
+            film.merge(tile);
+
+            for pixel in film.resolve().0 {
+                for (got, want) in [(pixel.0, value.0), (pixel.1, value.1), (pixel.2, value.2)] {
+                    assert!((got - want).abs() < 1e-4, "{kind:?}: {got} != {want}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::f32::consts::PI;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FilterKind {
+    /// Radius (in pixels) that gives a sensible result for each filter
+    pub fn default_radius(self) -> f32 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+/// Pixel reconstruction filter, used to weight how much a sample contributes
+/// to each of the pixels around it.
+#[derive(Copy, Clone, Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    /// Extent of the filter in pixels, in each direction from its center
+    pub radius: f32,
+}
+
+impl Filter {
+    /// `None` unless `radius` is positive and finite, as a filter with no
+    /// extent never weights any sample
+    pub fn new(kind: FilterKind, radius: f32) -> Option<Self> {
+        (radius > 0.0 && radius.is_finite()).then_some(Self { kind, radius })
+    }
+
+    /// Weight of a sample that is offset by (dx, dy) pixels from a pixel center
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let r = self.radius;
+        let x = x.abs();
+        if x > r {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                // Shifted down so that the filter goes to zero at its radius
+                let alpha = 2.0 / (r * r);
+                (-alpha * x * x).exp() - (-alpha * r * r).exp()
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / r, 1.0 / 3.0, 1.0 / 3.0),
+            FilterKind::Lanczos => sinc(x) * sinc(x / r),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            kind: FilterKind::Box,
+            radius: FilterKind::Box.default_radius(),
+        }
+    }
+}
+
+/// Mitchell-Netravali cubic, defined over [0, 2]
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    let x2 = x * x;
+    let x3 = x2 * x;
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b))
+            / 6.0
+    } else {
+        ((-b - 6.0 * c) * x3
+            + (6.0 * b + 30.0 * c) * x2
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::Lanczos,
+    ];
+
+    /// Midpoint-rule integral of the filter along one axis
+    fn integral_1d(filter: &Filter) -> f32 {
+        let steps = 10_000;
+        let dx = 2.0 * filter.radius / steps as f32;
+        (0..steps)
+            .map(|i| filter.evaluate_1d(-filter.radius + (i as f32 + 0.5) * dx) * dx)
+            .sum()
+    }
+
+    #[test]
+    fn rejects_radii_that_cover_nothing() {
+        assert!(Filter::new(FilterKind::Box, 0.0).is_none());
+        assert!(Filter::new(FilterKind::Tent, -1.0).is_none());
+        assert!(Filter::new(FilterKind::Gaussian, f32::NAN).is_none());
+        assert!(Filter::new(FilterKind::Mitchell, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn kernels_are_symmetric_and_vanish_outside_their_radius() {
+        for kind in KINDS {
+            let filter = Filter::new(kind, kind.default_radius()).unwrap();
+            let r = filter.radius;
+            assert!(filter.evaluate(0.0, 0.0) > 0.0, "{kind:?}");
+            assert_eq!(filter.evaluate(r * 1.01, 0.0), 0.0, "{kind:?}");
+            assert_eq!(filter.evaluate(0.0, -r * 1.01), 0.0, "{kind:?}");
+            for x in [0.1, 0.3, 0.7] {
+                let x = x * r;
+                assert_eq!(
+                    filter.evaluate(x, 0.2),
+                    filter.evaluate(-x, -0.2),
+                    "{kind:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn integrals_match_closed_forms() {
+        for r in [0.5, 1.0, 2.5] {
+            let integral = |kind| integral_1d(&Filter::new(kind, r).unwrap());
+            assert!((integral(FilterKind::Box) - 2.0 * r).abs() < 1e-3);
+            assert!((integral(FilterKind::Tent) - r * r).abs() < 1e-3);
+            // The Mitchell cubic integrates to 1 over [-2, 2], scaled to the radius
+            assert!((integral(FilterKind::Mitchell) - r / 2.0).abs() < 1e-3);
+        }
+        for kind in [FilterKind::Gaussian, FilterKind::Lanczos] {
+            assert!(integral_1d(&Filter::new(kind, kind.default_radius()).unwrap()) > 0.0);
+        }
+    }
+}
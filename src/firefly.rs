@@ -0,0 +1,78 @@
+use rayon::prelude::*;
+
+use crate::vec3::Vec3;
+
+/// Smallest spread of luminance that outliers are measured against, so that
+/// a pixel next to black ones isn't an outlier however dim it is. Keeps
+/// single-pixel highlights, such as small lights and glints, that are no
+/// brighter than white.
+const MIN_SPREAD: f32 = 1.0;
+
+/// Replaces isolated pixels that are much brighter than their neighbours with
+/// the average of those neighbours. A pixel is an outlier when its luminance
+/// is more than `threshold` standard deviations above the mean luminance of
+/// the surrounding 3x3 block, where the standard deviation is taken to be at
+/// least 1.
+pub fn reject_fireflies(image: &[Vec3], width: u32, height: u32, threshold: f32) -> Vec<Vec3> {
+    (0..(width * height) as usize)
+        .into_par_iter()
+        .map(|n| {
+            let x = (n as u32 % width) as i32;
+            let y = (n as u32 / width) as i32;
+
+            let neighbours: Vec<Vec3> = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+                .filter(|&(qx, qy)| (qx, qy) != (x, y))
+                .filter(|&(qx, qy)| qx >= 0 && qy >= 0 && qx < width as i32 && qy < height as i32)
+                .map(|(qx, qy)| image[(qy as u32 * width + qx as u32) as usize])
+                .collect();
+
+            if neighbours.is_empty() {
+                return image[n];
+            }
+
+            let count = neighbours.len() as f32;
+            let mean = neighbours.iter().map(|&c| c.luminance()).sum::<f32>() / count;
+            let variance = neighbours
+                .iter()
+                .map(|&c| (c.luminance() - mean).powi(2))
+                .sum::<f32>()
+                / count;
+            // Stops every slight bump in a flat region from counting as an outlier
+            let spread = variance.sqrt().max(0.1 * mean).max(MIN_SPREAD);
+
+            if image[n].luminance() > mean + threshold * spread {
+                neighbours.into_iter().sum::<Vec3>() / count
+            } else {
+                image[n]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5x5 image of `background` with `center` in the middle
+    fn image_with_center(background: Vec3, center: Vec3) -> Vec<Vec3> {
+        let mut image = vec![background; 25];
+        image[12] = center;
+        image
+    }
+
+    #[test]
+    fn keeps_highlights_on_black() {
+        let image = image_with_center(Vec3::default(), Vec3(1.0, 1.0, 1.0));
+        let result = reject_fireflies(&image, 5, 5, 3.0);
+        assert!(result == image);
+    }
+
+    #[test]
+    fn replaces_fireflies_with_their_neighbours() {
+        let background = Vec3(0.5, 0.5, 0.5);
+        let image = image_with_center(background, Vec3(50.0, 50.0, 50.0));
+        let result = reject_fireflies(&image, 5, 5, 3.0);
+        assert!(result == vec![background; 25]);
+    }
+}
@@ -1,19 +1,33 @@
 use std::ops::Range;
 
+use rand::Rng;
+
+use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord<'_>>;
 }
 
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     pub intersection: Vec3,
     pub normal: Vec3,
     /// param for the incident ray
     pub t: f32,
-    pub material: Material,
+    /// Surface coordinates of the intersection, each in [0, 1]
+    pub uv: (f32, f32),
+    pub material: &'a Material,
+    /// Index of the object that was hit within its `HittableList`
+    pub object_id: usize,
+    /// Index of the distinct material that was hit within its `HittableList`
+    pub material_id: usize,
+    /// Whether the ray arrived from the outer side of the surface: outside a
+    /// sphere, or on the side of a quad that `u × v` points towards. Quads'
+    /// normals always face the ray, so this is the only way to tell whether a
+    /// ray is leaving a box made of them.
+    pub front_face: bool,
 }
 
 pub struct Sphere {
@@ -22,8 +36,16 @@ pub struct Sphere {
     pub material: Material,
 }
 
+impl Sphere {
+    pub fn bounding_box(&self) -> Aabb {
+        let radius = self.radius.abs();
+        let corner = Vec3(radius, radius, radius);
+        Aabb::new(self.center - corner, self.center + corner)
+    }
+}
+
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord<'_>> {
         let oc = ray.origin - self.center;
         let a = ray.dir.dot(ray.dir);
         // In theory, b = 2 * dot(ray.dir, oc). However, this cancels out with
@@ -46,7 +68,11 @@ impl Hittable for Sphere {
                         intersection,
                         normal,
                         t,
-                        material: self.material,
+                        uv: sphere_uv(normal),
+                        material: &self.material,
+                        object_id: 0,
+                        material_id: 0,
+                        front_face: ray.dir.dot(normal) < 0.0,
                     });
                 }
             }
@@ -56,34 +82,160 @@ impl Hittable for Sphere {
     }
 }
 
+/// Maps a point on the unit sphere to spherical coordinates, scaled to [0, 1]
+fn sphere_uv(p: Vec3) -> (f32, f32) {
+    let theta = (-p.1).clamp(-1.0, 1.0).acos();
+    let phi = (-p.2).atan2(p.0) + std::f32::consts::PI;
+    (
+        phi / (2.0 * std::f32::consts::PI),
+        theta / std::f32::consts::PI,
+    )
+}
+
+/// A point sampled on the surface of a light source
+pub(crate) struct LightSample {
+    pub point: Vec3,
+    /// Outward normal of the light at `point`
+    pub normal: Vec3,
+    pub emission: Vec3,
+    /// Probability density (per unit area) of sampling this point, including
+    /// the chance of picking this particular light
+    pub pdf: f32,
+    pub object_id: usize,
+    pub both_sides: bool,
+}
+
+impl LightSample {
+    /// A cosine-weighted direction for light leaving the sampled point, along
+    /// with its density per unit solid angle. Lights that emit from both sides
+    /// pick a side at random.
+    pub fn sample_direction<R: Rng + ?Sized>(&self, rng: &mut R) -> (Vec3, f32) {
+        let mut normal = self.normal;
+        let mut side_pdf = 1.0;
+        if self.both_sides {
+            side_pdf = 0.5;
+            if rng.gen::<bool>() {
+                normal = -normal;
+            }
+        }
+
+        let mut dir = normal + Vec3::random_unit_vector(rng);
+        if dir.dot(dir) < 1e-8 {
+            dir = normal;
+        }
+        let dir = dir.normalize();
+        (dir, side_pdf * normal.dot(dir) / std::f32::consts::PI)
+    }
+}
+
 pub struct HittableList {
     hittables: Vec<AnyHittable>,
+    /// Index into `materials` for each of the hittables
+    material_ids: Vec<usize>,
+    materials: Vec<Material>,
+    /// Indices of the hittables that emit light
+    lights: Vec<usize>,
 }
 
 impl HittableList {
     pub fn new() -> Self {
         Self {
             hittables: Vec::new(),
+            material_ids: Vec::new(),
+            materials: Vec::new(),
+            lights: Vec::new(),
         }
     }
 
     pub fn push_sphere(&mut self, sphere: Sphere) {
-        self.hittables.push(AnyHittable::Sphere(sphere));
+        self.push_material(&sphere.material);
+        self.push_hittable(AnyHittable::Sphere(sphere));
     }
 
     pub fn push_quad(&mut self, quad: Quad) {
-        self.hittables.push(AnyHittable::Quad(quad));
+        self.push_material(&quad.material);
+        self.push_hittable(AnyHittable::Quad(quad));
+    }
+
+    fn push_hittable(&mut self, hittable: AnyHittable) {
+        if hittable.material().is_emissive() {
+            self.lights.push(self.hittables.len());
+        }
+        self.hittables.push(hittable);
+    }
+
+    /// Picks a light uniformly at random, then a point uniformly on its surface
+    pub(crate) fn sample_light<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        let object_id = self.lights[rng.gen_range(0..self.lights.len())];
+        let light = &self.hittables[object_id];
+        let (point, normal) = light.sample_point(rng);
+
+        Some(LightSample {
+            point,
+            normal,
+            emission: light.material().emit(point),
+            pdf: self.light_pdf(object_id),
+            object_id,
+            both_sides: self.emits_both_sides(object_id),
+        })
+    }
+
+    /// Probability density (per unit area) of `sample_light` picking any given
+    /// point on the object
+    pub(crate) fn light_pdf(&self, object_id: usize) -> f32 {
+        1.0 / (self.lights.len() as f32 * self.hittables[object_id].area())
+    }
+
+    /// Whether the object emits light from both of its sides, or just outwards
+    pub(crate) fn emits_both_sides(&self, object_id: usize) -> bool {
+        matches!(self.hittables[object_id], AnyHittable::Quad(_))
+    }
+
+    /// The box containing every object in the list, or `None` if it's empty
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.hittables
+            .iter()
+            .map(AnyHittable::bounding_box)
+            .reduce(Aabb::union)
+    }
+
+    /// Number of distinct materials used by the objects in the list
+    pub fn num_materials(&self) -> usize {
+        self.materials.len()
+    }
+
+    fn push_material(&mut self, material: &Material) {
+        let id = match self.materials.iter().position(|m| m == material) {
+            Some(id) => id,
+            None => {
+                self.materials.push(material.clone());
+                self.materials.len() - 1
+            }
+        };
+        self.material_ids.push(id);
+    }
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord<'_>> {
         let mut closest_hit = None;
         let mut closest_t = t_range.end;
 
-        for hittable in &self.hittables {
-            if let Some(hit_record) = hittable.hit(ray, t_range.start..closest_t) {
+        for (object_id, hittable) in self.hittables.iter().enumerate() {
+            if let Some(mut hit_record) = hittable.hit(ray, t_range.start..closest_t) {
                 closest_t = hit_record.t;
+                hit_record.object_id = object_id;
+                hit_record.material_id = self.material_ids[object_id];
                 closest_hit = Some(hit_record);
             }
         }
@@ -122,10 +274,16 @@ impl Quad {
             w,
         }
     }
+
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.q, self.q + self.u + self.v)
+            .include(self.q + self.u)
+            .include(self.q + self.v)
+    }
 }
 
 impl Hittable for Quad {
-    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord<'_>> {
         let denom = Vec3::dot(self.normal, ray.dir);
 
         if denom.abs() < 1e-8 {
@@ -149,7 +307,8 @@ impl Hittable for Quad {
         }
 
         // Can hit from either side of the plane
-        let normal = if Vec3::dot(ray.dir, self.normal) < 0.0 {
+        let front_face = Vec3::dot(ray.dir, self.normal) < 0.0;
+        let normal = if front_face {
             self.normal
         } else {
             -self.normal
@@ -159,7 +318,11 @@ impl Hittable for Quad {
             intersection,
             normal,
             t,
-            material: self.material,
+            uv: (alpha, beta),
+            material: &self.material,
+            object_id: 0,
+            material_id: 0,
+            front_face,
         })
     }
 }
@@ -174,8 +337,48 @@ enum AnyHittable {
     Quad(Quad),
 }
 
+impl AnyHittable {
+    fn material(&self) -> &Material {
+        match self {
+            AnyHittable::Sphere(sphere) => &sphere.material,
+            AnyHittable::Quad(quad) => &quad.material,
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            AnyHittable::Sphere(sphere) => sphere.bounding_box(),
+            AnyHittable::Quad(quad) => quad.bounding_box(),
+        }
+    }
+
+    fn area(&self) -> f32 {
+        match self {
+            AnyHittable::Sphere(sphere) => {
+                4.0 * std::f32::consts::PI * sphere.radius * sphere.radius
+            }
+            AnyHittable::Quad(quad) => quad.u.cross(quad.v).magnitude(),
+        }
+    }
+
+    /// A uniformly distributed point on the surface, along with the outward
+    /// normal there
+    fn sample_point<R: Rng + ?Sized>(&self, rng: &mut R) -> (Vec3, Vec3) {
+        match self {
+            AnyHittable::Sphere(sphere) => {
+                let normal = Vec3::random_unit_vector(rng);
+                (sphere.center + sphere.radius * normal, normal)
+            }
+            AnyHittable::Quad(quad) => {
+                let point = quad.q + rng.gen::<f32>() * quad.u + rng.gen::<f32>() * quad.v;
+                (point, quad.normal)
+            }
+        }
+    }
+}
+
 impl Hittable for AnyHittable {
-    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<HitRecord<'_>> {
         match self {
             AnyHittable::Sphere(sphere) => sphere.hit(ray, t_range),
             AnyHittable::Quad(quad) => quad.hit(ray, t_range),
@@ -0,0 +1,337 @@
+use std::sync::Mutex;
+
+use indicatif::ProgressBar;
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::aov::{Aov, FirstHit};
+use crate::film::Film;
+use crate::hittable::Hittable;
+use crate::output::RenderOutput;
+use crate::ray::Ray;
+use crate::subsurface::{medium_after, Medium};
+use crate::vec3::Vec3;
+use crate::{clamp_brightness, RenderSettings, Scene, BACKGROUND_COLOR};
+
+/// Number of image rows rendered together by a single task
+const TILE_ROWS: u32 = 8;
+
+/// A light transport algorithm, used by `Scene::render` to produce an image
+pub trait Integrator: Send + Sync {
+    fn render(
+        &self,
+        scene: &Scene,
+        height: u32,
+        width: u32,
+        settings: &RenderSettings,
+    ) -> RenderOutput;
+}
+
+/// An integrator that estimates the light along each camera ray on its own.
+/// Renders `num_samples` jittered rays per pixel, reconstructed with the
+/// settings' filter, along with any auxiliary passes.
+pub trait RayIntegrator: Send + Sync {
+    /// Estimates the light arriving at the camera along `ray`
+    fn radiance<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        rng: &mut R,
+    ) -> Radiance;
+}
+
+impl<T: RayIntegrator> Integrator for T {
+    fn render(
+        &self,
+        scene: &Scene,
+        height: u32,
+        width: u32,
+        settings: &RenderSettings,
+    ) -> RenderOutput {
+        let bar = ProgressBar::new(height as u64);
+        let film = Mutex::new(Film::new(width, height, settings.filter, &settings.aovs));
+
+        (0..height.div_ceil(TILE_ROWS))
+            .into_par_iter()
+            .for_each(|tile_index| {
+                let rows = (tile_index * TILE_ROWS)..((tile_index + 1) * TILE_ROWS).min(height);
+                let mut tile = film.lock().unwrap().tile(rows.clone());
+                let mut rng = rand::thread_rng();
+
+                for row in rows.clone() {
+                    for column in 0..width {
+                        for _ in 0..settings.num_samples {
+                            // Raster position, measured downwards from the top-left corner
+                            let raster_x = column as f32 + rng.gen::<f32>();
+                            let raster_y = row as f32 + rng.gen::<f32>();
+
+                            let x = raster_x / width as f32;
+                            let y = 1.0 - raster_y / height as f32;
+
+                            let Some(ray) = &scene.camera.get_ray(x, y, &mut rng) else {
+                                // Still counts towards the pixel, which stays black
+                                tile.add_sample(raster_x, raster_y, Vec3::default());
+                                continue;
+                            };
+                            let radiance = self.radiance(ray, scene, settings, &mut rng);
+                            tile.add_sample(raster_x, raster_y, radiance.total());
+
+                            for (layer, aov) in settings.aovs.iter().enumerate() {
+                                if let Some(value) = aov.sample_value(&radiance) {
+                                    tile.add_aov_sample(layer, column, row, value);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                film.lock().unwrap().merge(tile);
+                bar.inc(rows.len() as u64);
+            });
+
+        bar.finish();
+
+        let (beauty, aovs) = film.into_inner().unwrap().resolve();
+        RenderOutput {
+            width,
+            height,
+            beauty,
+            aovs,
+        }
+    }
+}
+
+/// Renders the auxiliary passes taken from the first hit of each camera ray,
+/// for integrators that don't follow camera rays one pixel sample at a time.
+/// Passes that measure light are left out.
+pub(crate) fn render_hit_aovs(
+    scene: &Scene,
+    height: u32,
+    width: u32,
+    settings: &RenderSettings,
+) -> Vec<(Aov, Vec<Vec3>)> {
+    let aovs: Vec<Aov> = settings
+        .aovs
+        .iter()
+        .copied()
+        .filter(|aov| aov.needs_hit())
+        .collect();
+    if aovs.is_empty() {
+        return Vec::new();
+    }
+    let film = Mutex::new(Film::new(width, height, settings.filter, &aovs));
+
+    (0..height.div_ceil(TILE_ROWS))
+        .into_par_iter()
+        .for_each(|tile_index| {
+            let rows = (tile_index * TILE_ROWS)..((tile_index + 1) * TILE_ROWS).min(height);
+            let mut tile = film.lock().unwrap().tile(rows.clone());
+            let mut rng = rand::thread_rng();
+
+            for row in rows {
+                for column in 0..width {
+                    for _ in 0..settings.num_samples {
+                        let x = (column as f32 + rng.gen::<f32>()) / width as f32;
+                        let y = 1.0 - (row as f32 + rng.gen::<f32>()) / height as f32;
+                        let Some(ray) = &scene.camera.get_ray(x, y, &mut rng) else {
+                            continue;
+                        };
+                        let radiance = Radiance {
+                            first_hit: scene
+                                .objects
+                                .hit(ray, 0.001..f32::MAX)
+                                .map(|hit| FirstHit::new(ray, &hit)),
+                            ..Default::default()
+                        };
+                        for (layer, aov) in aovs.iter().enumerate() {
+                            if let Some(value) = aov.sample_value(&radiance) {
+                                tile.add_aov_sample(layer, column, row, value);
+                            }
+                        }
+                    }
+                }
+            }
+
+            film.lock().unwrap().merge(tile);
+        });
+
+    film.into_inner().unwrap().resolve().1
+}
+
+/// Light arriving along a camera ray, split by the number of bounces it took
+#[derive(Copy, Clone, Default)]
+pub struct Radiance {
+    /// Emitted by the first surface hit
+    pub emission: Vec3,
+    /// Emitted by the second surface hit
+    pub direct: Vec3,
+    /// Emitted by any later surface
+    pub indirect: Vec3,
+    /// The first surface hit, for the auxiliary passes that describe it.
+    /// `None` if the ray hit nothing.
+    pub first_hit: Option<FirstHit>,
+}
+
+impl Radiance {
+    pub fn total(&self) -> Vec3 {
+        self.emission + self.direct + self.indirect
+    }
+}
+
+/// Unidirectional path tracing from the camera
+pub struct PathTracer;
+
+impl RayIntegrator for PathTracer {
+    fn radiance<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        rng: &mut R,
+    ) -> Radiance {
+        trace_path(ray, scene, settings, rng).0
+    }
+}
+
+/// Follows a camera ray as it scatters around the scene, returning the light
+/// it picks up and the number of bounces it took
+pub(crate) fn trace_path<R: Rng + ?Sized>(
+    ray: &Ray,
+    scene: &Scene,
+    settings: &RenderSettings,
+    rng: &mut R,
+) -> (Radiance, u32) {
+    let mut radiance = Radiance {
+        emission: BACKGROUND_COLOR,
+        ..Default::default()
+    };
+    let mut ray: Ray = ray.to_owned();
+    let mut ray_color = Vec3(1.0, 1.0, 1.0);
+    // Medium of the subsurface material the path is inside, if any
+    let mut medium: Option<Medium> = None;
+
+    for bounce in 0..settings.depth {
+        let hit = match medium {
+            Some(medium) => {
+                medium
+                    .random_walk(&mut ray, &scene.objects, rng)
+                    .map(|(hit_record, weight)| {
+                        ray_color = ray_color * weight;
+                        hit_record
+                    })
+            }
+            // Start t_range at non-zero value to prevent self-intersection
+            None => scene.objects.hit(&ray, 0.001..f32::MAX),
+        };
+        if let Some(hit_record) = hit {
+            if bounce == 0 {
+                radiance.first_hit = Some(FirstHit::new(&ray, &hit_record));
+            }
+            let mut light = hit_record.material.emit(hit_record.intersection) * ray_color;
+            if let (Some(max), true) = (settings.indirect_clamp, bounce > 0) {
+                light = clamp_brightness(light, max);
+            }
+            match bounce {
+                0 => radiance.emission = radiance.emission + light,
+                1 => radiance.direct = radiance.direct + light,
+                _ => radiance.indirect = radiance.indirect + light,
+            }
+
+            if let Some((new_ray, attenuation)) =
+                hit_record.material.scatter(&ray, &hit_record, rng)
+            {
+                ray_color = ray_color * attenuation;
+                medium = medium_after(medium, &hit_record, &ray, &new_ray);
+                ray = new_ray;
+
+                if settings
+                    .russian_roulette
+                    .is_some_and(|min| bounce + 1 >= min)
+                {
+                    // Paths that can carry more light are more likely to survive,
+                    // and are weighted up to make up for the ones that don't
+                    let survival = ray_color.reduce(f32::max).clamp(0.05, 1.0);
+                    if rng.gen::<f32>() >= survival {
+                        return (radiance, bounce + 1);
+                    }
+                    ray_color = ray_color / survival;
+                }
+            } else {
+                // Ray hit something, but no scatter to follow
+                return (radiance, bounce);
+            }
+        } else {
+            // Ray hit nothing
+            return (radiance, bounce);
+        }
+    }
+
+    (radiance, settings.depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdpt::Bidirectional;
+    use crate::camera::Camera;
+    use crate::debug::Normals;
+    use crate::hittable::{HittableList, Quad};
+    use crate::material::Material;
+    use crate::spectral::SpectralPathTracer;
+    use crate::texture::Texture;
+
+    #[test]
+    fn first_hit_passes_come_from_the_integrators_own_rays() {
+        let color = Vec3(0.2, 0.4, 0.6);
+        let mut objects = HittableList::new();
+        objects.push_quad(Quad::new(
+            Vec3(-2.0, -2.0, 0.0),
+            Vec3(4.0, 0.0, 0.0),
+            Vec3(0.0, 4.0, 0.0),
+            Material::Lambertian(Texture::Constant(color)),
+        ));
+        let scene = Scene {
+            objects,
+            camera: Camera::orthographic(
+                Vec3(0.0, 0.0, 5.0),
+                Vec3(0.0, 0.0, 0.0),
+                Vec3(0.0, 1.0, 0.0),
+                1.0,
+                1.0,
+            ),
+        };
+        let integrators: [Box<dyn Integrator>; 4] = [
+            Box::new(PathTracer),
+            Box::new(SpectralPathTracer),
+            Box::new(Bidirectional),
+            Box::new(Normals),
+        ];
+
+        for integrator in integrators {
+            let settings = RenderSettings {
+                num_samples: 2,
+                depth: 3,
+                aovs: vec![Aov::Depth, Aov::Albedo, Aov::ObjectId],
+                integrator,
+                ..Default::default()
+            };
+            let output = scene.render(4, 4, &settings);
+
+            for (aov, expected) in [
+                (Aov::Depth, Vec3(5.0, 5.0, 5.0)),
+                (Aov::Albedo, color),
+                (Aov::ObjectId, Vec3(0.0, 0.0, 0.0)),
+            ] {
+                let (_, values) = output.aovs.iter().find(|(a, _)| *a == aov).unwrap();
+                for value in values {
+                    assert!(
+                        (*value - expected).magnitude() < 1e-4,
+                        "{} pass is off",
+                        aov.name()
+                    );
+                }
+            }
+        }
+    }
+}
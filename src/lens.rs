@@ -0,0 +1,359 @@
+//! Realistic lens simulation. Rays are traced from the film through each
+//! surface of a lens prescription, so that distortion, vignetting and the
+//! change in field of view as the lens focuses all follow from its design.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rand::Rng;
+
+use crate::material::refract;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// One spherical surface of a lens, or its aperture stop. Lengths are in
+/// millimetres.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LensElement {
+    /// Radius of curvature, positive when the centre of curvature is towards
+    /// the film. Zero for the aperture stop.
+    pub radius: f32,
+    /// Distance along the axis to the next surface, towards the film
+    pub thickness: f32,
+    /// Refractive index between this surface and the next. Zero is treated as
+    /// air.
+    pub ior: f32,
+    /// Diameter of the surface
+    pub aperture: f32,
+}
+
+/// A lens prescription, listing its surfaces from the front of the lens to the
+/// back
+#[derive(Clone, Debug, PartialEq)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+}
+
+impl LensSystem {
+    /// Fails if there are no elements
+    pub fn new(elements: Vec<LensElement>) -> io::Result<Self> {
+        if elements.is_empty() {
+            return Err(invalid_data("no lens elements".to_string()));
+        }
+        Ok(Self { elements })
+    }
+
+    /// Loads a lens from a text file with one surface per line, each giving
+    /// the radius, thickness, refractive index and aperture diameter separated
+    /// by whitespace. Lines starting with `#` are ignored.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Reads a lens in the format taken by `open`
+    fn parse(text: &str) -> io::Result<Self> {
+        let mut elements = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|err| invalid_data(format!("{err} in `{line}`")))?;
+            let [radius, thickness, ior, aperture] = values[..] else {
+                return Err(invalid_data(format!("expected 4 values in `{line}`")));
+            };
+            elements.push(LensElement {
+                radius,
+                thickness,
+                ior,
+                aperture,
+            });
+        }
+
+        Self::new(elements)
+    }
+
+    /// Positions of the surfaces along the axis, with the film at zero and the
+    /// scene towards +z, when the back surface is `film_distance` from the film
+    fn positions(&self, film_distance: f32) -> Vec<f32> {
+        let mut positions = vec![film_distance; self.elements.len()];
+        for i in (0..self.elements.len() - 1).rev() {
+            positions[i] = positions[i + 1] + self.elements[i].thickness;
+        }
+        positions
+    }
+
+    /// Follows a ray through the surfaces, in either direction. `None` if the
+    /// ray is blocked, or reflected back by total internal reflection.
+    fn trace(&self, positions: &[f32], ray: Ray, towards_scene: bool) -> Option<Ray> {
+        let mut ray = Ray {
+            origin: ray.origin,
+            dir: ray.dir.normalize(),
+        };
+        let order: Vec<usize> = if towards_scene {
+            (0..self.elements.len()).rev().collect()
+        } else {
+            (0..self.elements.len()).collect()
+        };
+
+        for i in order {
+            let element = &self.elements[i];
+            let z = positions[i];
+            let half_aperture = element.aperture / 2.0;
+
+            if element.radius == 0.0 {
+                let t = (z - ray.origin.2) / ray.dir.2;
+                let hit = ray.at_param(t);
+                if t < 0.0 || hit.0 * hit.0 + hit.1 * hit.1 > half_aperture * half_aperture {
+                    return None;
+                }
+                ray.origin = hit;
+                continue;
+            }
+
+            let centre = Vec3(0.0, 0.0, z - element.radius);
+            let t = intersect_surface(&ray, centre, element.radius)?;
+            let hit = ray.at_param(t);
+            if hit.0 * hit.0 + hit.1 * hit.1 > half_aperture * half_aperture {
+                return None;
+            }
+
+            // Refractive indices in front of and behind the surface
+            let front_ior = match i {
+                0 => 1.0,
+                _ => air_if_zero(self.elements[i - 1].ior),
+            };
+            let back_ior = air_if_zero(element.ior);
+            let ratio = if towards_scene {
+                back_ior / front_ior
+            } else {
+                front_ior / back_ior
+            };
+
+            let mut normal = (hit - centre).normalize();
+            if normal.dot(ray.dir) > 0.0 {
+                normal = -normal;
+            }
+            ray = Ray {
+                origin: hit,
+                dir: refract(ray.dir, normal, ratio)?.normalize(),
+            };
+        }
+
+        Some(ray)
+    }
+
+    /// Distance from the back surface to the film that brings objects
+    /// `distance` in front of the front surface into focus
+    fn focus(&self, distance: f32) -> f32 {
+        // Trace a ray close to the axis from the object, and see where it
+        // crosses the axis again behind the lens
+        let reference = self.elements[self.elements.len() - 1].thickness;
+        let positions = self.positions(reference);
+        let height = 0.01 * self.elements[0].aperture / 2.0;
+        let object = Vec3(0.0, 0.0, positions[0] + distance);
+        let ray = Ray {
+            origin: object,
+            dir: Vec3(height, 0.0, positions[0]) - object,
+        };
+
+        let crossing = self
+            .trace(&positions, ray, false)
+            .filter(|ray| ray.dir.0 != 0.0)
+            .map(|ray| ray.origin.2 - ray.origin.0 / ray.dir.0 * ray.dir.2);
+        match crossing {
+            Some(crossing) if crossing < positions[positions.len() - 1] => reference - crossing,
+            // The lens doesn't focus at that distance, so leave it as it is
+            _ => reference,
+        }
+    }
+}
+
+/// A lens system focused at a particular distance, in front of a film
+#[derive(Clone, Debug)]
+pub(crate) struct FocusedLens {
+    lens: LensSystem,
+    positions: Vec<f32>,
+    film_width: f32,
+    film_height: f32,
+}
+
+impl FocusedLens {
+    pub fn new(lens: LensSystem, focus_distance: f32, film_width: f32, film_height: f32) -> Self {
+        let film_distance = lens.focus(focus_distance);
+        let positions = lens.positions(film_distance);
+        Self {
+            lens,
+            positions,
+            film_width,
+            film_height,
+        }
+    }
+
+    /// The same lens, with the film moved to focus `focus_distance` in front of
+    /// it
+    pub fn refocus(&self, focus_distance: f32) -> Self {
+        Self::new(
+            self.lens.clone(),
+            focus_distance,
+            self.film_width,
+            self.film_height,
+        )
+    }
+
+    /// Position of the front surface along the axis
+    pub fn front(&self) -> f32 {
+        self.positions[0]
+    }
+
+    /// The ray leaving the front of the lens for light reaching the film at
+    /// `x` and `y`, in millimetres with the scene towards +z. Picks a point
+    /// on the back surface uniformly, so rays that are blocked on the way out
+    /// darken the image.
+    pub fn sample_ray<R: Rng + ?Sized>(&self, x: f32, y: f32, rng: &mut R) -> Option<Ray> {
+        let back = self.lens.elements.len() - 1;
+        let radius = self.lens.elements[back].aperture / 2.0;
+        let on_back = radius * Vec3::random_in_unit_disc(rng);
+        self.trace_from_film(x, y, on_back)
+    }
+
+    /// The ray leaving the front of the lens for light reaching the film at
+    /// `x` and `y` through the centre of the back surface
+    pub fn central_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        self.trace_from_film(x, y, Vec3::default())
+    }
+
+    /// Fraction of the light reaching the centre of the film that makes it
+    /// through the lens
+    pub fn transmission_at_centre(&self) -> f32 {
+        let back = self.lens.elements.len() - 1;
+        let radius = self.lens.elements[back].aperture / 2.0;
+
+        const STEPS: i32 = 64;
+        let (mut inside, mut passed) = (0, 0);
+        for i in 0..STEPS {
+            for j in 0..STEPS {
+                let a = 2.0 * (i as f32 + 0.5) / STEPS as f32 - 1.0;
+                let b = 2.0 * (j as f32 + 0.5) / STEPS as f32 - 1.0;
+                if a * a + b * b > 1.0 {
+                    continue;
+                }
+                inside += 1;
+                if self
+                    .trace_from_film(0.5, 0.5, radius * Vec3(a, b, 0.0))
+                    .is_some()
+                {
+                    passed += 1;
+                }
+            }
+        }
+        passed as f32 / inside as f32
+    }
+
+    fn trace_from_film(&self, x: f32, y: f32, on_back: Vec3) -> Option<Ray> {
+        // The lens flips the image, so the film is flipped to match
+        let film = Vec3(
+            -(x - 0.5) * self.film_width,
+            -(y - 0.5) * self.film_height,
+            0.0,
+        );
+        let back = self.lens.elements.len() - 1;
+        let target = Vec3(on_back.0, on_back.1, self.positions[back]);
+        let ray = Ray {
+            origin: film,
+            dir: target - film,
+        };
+        self.lens.trace(&self.positions, ray, true)
+    }
+}
+
+/// Distance along the ray to the spherical surface with the given centre and
+/// signed radius, picking the intersection on the cap facing the axis's end
+fn intersect_surface(ray: &Ray, centre: Vec3, radius: f32) -> Option<f32> {
+    let oc = ray.origin - centre;
+    let b = oc.dot(ray.dir);
+    let c = oc.dot(oc) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let (t0, t1) = (-b - discriminant.sqrt(), -b + discriminant.sqrt());
+    // The surface's vertex is on the side of the sphere facing away from its
+    // centre, which is the nearer side for rays heading towards the centre
+    let heading_to_centre = (ray.dir.2 > 0.0) == (radius < 0.0);
+    let t = if heading_to_centre { t0 } else { t1 };
+    (t > 0.0).then_some(t)
+}
+
+fn air_if_zero(ior: f32) -> f32 {
+    if ior == 0.0 {
+        1.0
+    } else {
+        ior
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_surfaces_skipping_comments_and_blank_lines() {
+        let lens = LensSystem::parse(
+            "# radius thickness ior aperture\n\n  50.0 5.0 1.5 20.0\n0 2 0 10\n-50 0 1 20\n",
+        )
+        .unwrap();
+        assert_eq!(lens.elements.len(), 3);
+        assert_eq!(
+            lens.elements[0],
+            LensElement {
+                radius: 50.0,
+                thickness: 5.0,
+                ior: 1.5,
+                aperture: 20.0,
+            }
+        );
+        assert_eq!(lens.elements[1].radius, 0.0);
+    }
+
+    #[test]
+    fn rejects_malformed_lenses() {
+        for text in [
+            "",
+            "# only a comment",
+            "50 5 1.5",
+            "50 5 1.5 20 1",
+            "50 five 1.5 20",
+        ] {
+            let err = LensSystem::parse(text).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{text:?}");
+        }
+        assert!(LensSystem::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn loads_the_bundled_double_gauss() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/lenses/dgauss.50mm.dat");
+        let lens = LensSystem::open(path).unwrap();
+        assert_eq!(lens.elements.len(), 11);
+        assert_eq!(lens.elements.iter().filter(|e| e.radius == 0.0).count(), 1);
+
+        // The ray through the middle of the lens stays on its axis
+        let focused = FocusedLens::new(lens, 5000.0, 36.0, 24.0);
+        let ray = focused.central_ray(0.5, 0.5).unwrap();
+        let dir = ray.dir.normalize();
+        assert!(dir.0.abs() < 1e-4 && dir.1.abs() < 1e-4 && dir.2 > 0.0);
+    }
+}
@@ -1,21 +1,47 @@
-use indicatif::ProgressBar;
-use rand::Rng;
-use rayon::prelude::*;
-
+mod aabb;
+mod aov;
+mod aperture;
+mod bdpt;
 mod camera;
+mod debug;
+mod denoise;
+mod film;
+mod filter;
+mod firefly;
 mod hittable;
+mod integrator;
+mod lens;
 mod material;
+mod mlt;
+mod output;
+mod photon;
 mod ray;
+mod spectral;
+mod subsurface;
 mod texture;
+mod thin_film;
 mod vec3;
 
-use hittable::Hittable;
-
-pub use camera::Camera;
-pub use hittable::{HittableList, Quad, Sphere};
-pub use material::Material;
+pub use aabb::Aabb;
+pub use aov::{Aov, FirstHit};
+pub use aperture::{ApertureMask, ApertureShape};
+pub use bdpt::Bidirectional;
+pub use camera::{frame_bounds, Camera, FisheyeMapping, PhysicalCamera, Stereo, StereoLayout};
+pub use debug::{Albedo, AmbientOcclusion, BounceCount, Depth, Normals};
+pub use denoise::{denoise, DenoiseGuides, DenoiseSettings};
+pub use filter::{Filter, FilterKind};
+pub use firefly::reject_fireflies;
+pub use hittable::{HitRecord, Hittable, HittableList, Quad, Sphere};
+pub use integrator::{Integrator, PathTracer, Radiance, RayIntegrator};
+pub use lens::{LensElement, LensSystem};
+pub use material::{Dispersion, Material};
+pub use mlt::Metropolis;
+pub use output::RenderOutput;
+pub use photon::ProgressivePhotonMapping;
 pub use ray::Ray;
+pub use spectral::SpectralPathTracer;
 pub use texture::Texture;
+pub use thin_film::ThinFilm;
 pub use vec3::Vec3;
 
 const BACKGROUND_COLOR: Vec3 = Vec3(0.0, 0.0, 0.0);
@@ -25,68 +51,57 @@ pub struct Scene {
     pub camera: Camera,
 }
 
-impl Scene {
-    pub fn render(&self, height: u32, width: u32, num_samples: u32, depth: u32) -> Vec<Vec3> {
-        let count = std::sync::atomic::AtomicU32::new(0);
-        let bar = ProgressBar::new(100);
-
-        let num_pixels = height * width;
-        let pixels = (0..num_pixels)
-            .into_par_iter()
-            .map(|n| {
-                let i = n % width;
-                let j = height - (n / width) - 1;
-                let mut rng = rand::thread_rng();
-                let col_sum: Vec3 = (0..num_samples)
-                    .map(|_| {
-                        let x = (i as f32 + rng.gen::<f32>()) / width as f32;
-                        let y = (j as f32 + rng.gen::<f32>()) / height as f32;
-
-                        let ray = &self.camera.get_ray(x, y);
-                        color(ray, &self.objects, depth)
-                    })
-                    .sum();
-
-                let col = col_sum / (num_samples as f32);
-
-                let prev_count = count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                let section = height * width / 100;
-                if (prev_count + 1) % section == 0 {
-                    bar.inc(1)
-                }
-
-                col
-            })
-            .collect();
-
-        bar.finish();
+pub struct RenderSettings {
+    /// Ray samples per pixel
+    pub num_samples: u32,
+    /// Bounces per ray
+    pub depth: u32,
+    pub filter: Filter,
+    /// Auxiliary passes to render alongside the beauty image
+    pub aovs: Vec<Aov>,
+    /// Maximum brightness of light picked up after the first bounce of a
+    /// sample. Suppresses fireflies at the cost of some bias.
+    pub indirect_clamp: Option<f32>,
+    /// Number of bounces after which paths are randomly terminated based on
+    /// how much light they can still carry. Unbiased, and makes a high `depth`
+    /// affordable.
+    pub russian_roulette: Option<u32>,
+    /// Light transport algorithm used to render the image
+    pub integrator: Box<dyn Integrator>,
+}
 
-        pixels
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            num_samples: 10,
+            depth: 10,
+            filter: Filter::default(),
+            aovs: Vec::new(),
+            indirect_clamp: None,
+            russian_roulette: None,
+            integrator: Box::new(PathTracer),
+        }
     }
 }
 
-fn color(ray: &Ray, objects: &HittableList, depth: u32) -> Vec3 {
-    let mut light = BACKGROUND_COLOR;
-    let mut ray: Ray = ray.to_owned();
-    let mut ray_color = Vec3(1.0, 1.0, 1.0);
-
-    for _ in 0..depth {
-        // Start t_range at non-zero value to prevent self-intersection
-        if let Some(hit_record) = objects.hit(&ray, 0.001..std::f32::MAX) {
-            light = light + hit_record.material.emit() * ray_color;
-
-            if let Some((new_ray, attenuation)) = hit_record.material.scatter(&ray, &hit_record) {
-                ray_color = ray_color * attenuation;
-                ray = new_ray;
-            } else {
-                // Ray hit something, but no scatter to follow
-                break;
-            }
-        } else {
-            // Ray hit nothing
-            break;
+impl Scene {
+    pub fn render(&self, height: u32, width: u32, settings: &RenderSettings) -> RenderOutput {
+        let mut output = settings.integrator.render(self, height, width, settings);
+        let exposure = self.camera.exposure();
+        if exposure != 1.0 {
+            output.apply_exposure(exposure);
         }
+        output
     }
+}
 
-    light
+/// Scales down a colour so that none of its components exceed `max`,
+/// preserving its hue
+fn clamp_brightness(col: Vec3, max: f32) -> Vec3 {
+    let brightest = col.reduce(f32::max);
+    if brightest > max {
+        (max / brightest) * col
+    } else {
+        col
+    }
 }
@@ -1,14 +1,41 @@
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
 use image::error::ImageError;
 use rand::Rng;
 
+use ray_tracer::frame_bounds;
+use ray_tracer::reject_fireflies;
+use ray_tracer::Albedo;
+use ray_tracer::AmbientOcclusion;
+use ray_tracer::Aov;
+use ray_tracer::ApertureMask;
+use ray_tracer::ApertureShape;
+use ray_tracer::Bidirectional;
+use ray_tracer::BounceCount;
 use ray_tracer::Camera;
+use ray_tracer::DenoiseSettings;
+use ray_tracer::Depth;
+use ray_tracer::Dispersion;
+use ray_tracer::Filter;
+use ray_tracer::FilterKind;
+use ray_tracer::FisheyeMapping;
 use ray_tracer::HittableList;
+use ray_tracer::LensSystem;
 use ray_tracer::Material;
+use ray_tracer::Metropolis;
+use ray_tracer::Normals;
+use ray_tracer::PathTracer;
+use ray_tracer::PhysicalCamera;
+use ray_tracer::ProgressivePhotonMapping;
 use ray_tracer::Quad;
+use ray_tracer::RenderSettings;
 use ray_tracer::Scene;
+use ray_tracer::SpectralPathTracer;
 use ray_tracer::Sphere;
+use ray_tracer::Stereo;
+use ray_tracer::StereoLayout;
 use ray_tracer::Texture;
+use ray_tracer::ThinFilm;
 use ray_tracer::Vec3;
 
 #[derive(Parser, Debug)]
@@ -33,6 +60,417 @@ struct Args {
     /// Which scene to render
     #[arg(long, value_enum, default_value_t = SceneArg::Spheres)]
     scene: SceneArg,
+
+    #[command(flatten)]
+    camera: CameraArgs,
+
+    /// Light transport algorithm
+    #[arg(long, value_enum, default_value_t = IntegratorArg::Path)]
+    integrator: IntegratorArg,
+
+    /// Photons traced per iteration with `--integrator sppm` [default: one per pixel]
+    #[arg(long)]
+    photons: Option<u32>,
+
+    /// Initial photon gather radius with `--integrator sppm` [default: based on scene scale]
+    #[arg(long)]
+    photon_radius: Option<f32>,
+
+    /// Probability of each mutation picking a new path with `--integrator mlt`
+    #[arg(long, default_value_t = 0.3)]
+    large_step_probability: f32,
+
+    /// Size of small mutations with `--integrator mlt`
+    #[arg(long, default_value_t = 0.01)]
+    mutation_size: f32,
+
+    /// Rays traced from each hit with `--integrator ao`
+    #[arg(long, default_value_t = 16)]
+    ao_samples: u32,
+
+    /// Distance beyond which geometry doesn't occlude with `--integrator ao`,
+    /// or fades to black with `--integrator depth` [default: unlimited for
+    /// `ao`, twice the focus distance for `depth`]
+    #[arg(long)]
+    max_distance: Option<f32>,
+
+    /// Pixel reconstruction filter
+    #[arg(long, value_enum, default_value_t = FilterArg::Box)]
+    filter: FilterArg,
+
+    /// Radius of the reconstruction filter in pixels [default: depends on filter]
+    #[arg(long, value_parser = parse_filter_radius)]
+    filter_radius: Option<f32>,
+
+    /// Auxiliary pass to render alongside the image, can be repeated
+    #[arg(long, value_enum)]
+    aov: Vec<AovArg>,
+
+    /// How to write the auxiliary passes
+    #[arg(long, value_enum, default_value_t = AovOutputArg::Separate)]
+    aov_output: AovOutputArg,
+
+    /// Randomly terminate paths that carry little light after this many bounces
+    #[arg(long, value_name = "MIN_BOUNCES")]
+    russian_roulette: Option<u32>,
+
+    /// Clamp the brightness of light picked up after the first bounce of each
+    /// sample to this value, to suppress fireflies
+    #[arg(long)]
+    clamp_indirect: Option<f32>,
+
+    /// Replace pixels that are brighter than their neighbours by more than this
+    /// many standard deviations
+    #[arg(long)]
+    reject_fireflies: Option<f32>,
+
+    /// Denoise the image, guided by the albedo, normal and depth at each pixel
+    #[arg(long)]
+    denoise: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct CameraArgs {
+    /// Camera projection
+    #[arg(long, value_enum, default_value_t = ProjectionArg::Perspective)]
+    projection: ProjectionArg,
+
+    /// Width of the view with `--projection orthographic` [default: width of
+    /// the perspective view at the focus distance]
+    #[arg(long)]
+    view_width: Option<f32>,
+
+    /// Field of view in degrees with `--projection fisheye`
+    #[arg(long, default_value_t = 180.0)]
+    fisheye_fov: f32,
+
+    /// Lens mapping with `--projection fisheye`
+    #[arg(long, value_enum, default_value_t = FisheyeMappingArg::Equidistant)]
+    fisheye_mapping: FisheyeMappingArg,
+
+    /// Render an image for each eye, packed into the frame. With `--projection
+    /// equirectangular`, renders an omni-directional stereo panorama.
+    #[arg(long, value_enum)]
+    stereo: Option<StereoLayoutArg>,
+
+    /// Distance between the eyes with `--stereo` [default: a 30th of the
+    /// convergence distance]
+    #[arg(long)]
+    eye_separation: Option<f32>,
+
+    /// Distance that appears at the depth of the screen with `--stereo`
+    /// [default: the focus distance]
+    #[arg(long)]
+    convergence: Option<f32>,
+
+    /// Number of aperture blades, giving polygonal out-of-focus highlights
+    /// [default: a circular aperture]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(3..))]
+    aperture_blades: Option<u32>,
+
+    /// Rotation of the aperture blades in degrees
+    #[arg(long, default_value_t = 0.0)]
+    aperture_rotation: f32,
+
+    /// Greyscale image of the aperture's shape, used instead of blades
+    #[arg(long, value_name = "PATH", value_parser = |path: &str| ApertureMask::open(path))]
+    aperture_mask: Option<ApertureMask>,
+
+    /// Strength of cat's eye vignetting, where the lens barrel clips the
+    /// aperture towards the edges of the image
+    #[arg(long)]
+    cats_eye: Option<f32>,
+
+    /// Derive the field of view, depth of field and exposure of a perspective
+    /// camera from real camera settings, with light in cd/m². Other
+    /// projections only take the exposure.
+    #[arg(long, conflicts_with = "lens")]
+    physical: bool,
+
+    /// Aperture f-number with `--physical`
+    #[arg(long, default_value_t = 2.8)]
+    f_number: f32,
+
+    /// Focal length in millimetres with `--physical`
+    #[arg(long, default_value_t = 50.0)]
+    focal_length: f32,
+
+    /// Sensor width in millimetres with `--physical` or `--lens`
+    #[arg(long, default_value_t = 36.0)]
+    sensor_width: f32,
+
+    /// Shutter speed in seconds with `--physical`
+    #[arg(long, default_value_t = 1.0 / 125.0)]
+    shutter_speed: f32,
+
+    /// Sensor sensitivity with `--physical`
+    #[arg(long, default_value_t = 100.0)]
+    iso: f32,
+
+    /// Length of one scene unit in metres with `--physical` or `--lens`
+    #[arg(long, default_value_t = 1.0)]
+    metres_per_unit: f32,
+
+    /// Lens prescription to trace perspective camera rays through, with a
+    /// surface per line giving its radius, thickness, refractive index and
+    /// aperture in millimetres. The lens's own surfaces give the aperture its
+    /// shape.
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = |path: &str| LensSystem::open(path),
+        conflicts_with_all = ["aperture_blades", "aperture_mask", "cats_eye"]
+    )]
+    lens: Option<LensSystem>,
+
+    /// Focus on whatever is at the centre of the image, or at `--focus-pixel`
+    #[arg(long)]
+    autofocus: bool,
+
+    /// Pixel to focus on with `--autofocus`, measured from the top-left corner
+    #[arg(long, num_args = 2, value_names = ["X", "Y"])]
+    focus_pixel: Option<Vec<u32>>,
+
+    /// Focus on the scene's main subject
+    #[arg(long, conflicts_with = "autofocus")]
+    focus_on_subject: bool,
+
+    /// Move the camera back to frame every object in the scene, looking in
+    /// the scene's view direction or along `--frame-direction`
+    #[arg(long)]
+    frame: bool,
+
+    /// Direction to look in with `--frame`
+    #[arg(long, num_args = 3, value_names = ["X", "Y", "Z"], allow_negative_numbers = true)]
+    frame_direction: Option<Vec<f32>>,
+
+    /// Space left around the scene with `--frame`, as a fraction of its size
+    #[arg(long, default_value_t = 0.1)]
+    frame_margin: f32,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum StereoLayoutArg {
+    /// Left eye on the left, right eye on the right
+    SideBySide,
+    /// Left eye on the top, right eye on the bottom
+    TopBottom,
+}
+
+impl From<StereoLayoutArg> for StereoLayout {
+    fn from(arg: StereoLayoutArg) -> Self {
+        match arg {
+            StereoLayoutArg::SideBySide => StereoLayout::SideBySide,
+            StereoLayoutArg::TopBottom => StereoLayout::TopBottom,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ProjectionArg {
+    /// Thin lens camera, where further objects look smaller
+    Perspective,
+    /// Parallel rays, for elevations and isometric views
+    Orthographic,
+    /// 360° latitude-longitude panorama, best with a 2:1 image
+    Equirectangular,
+    /// Circular fisheye image
+    Fisheye,
+    /// Six cube map faces in a 3x2 grid, best with a 3:2 image
+    Cubemap,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum FisheyeMappingArg {
+    Equidistant,
+    Equisolid,
+}
+
+impl From<FisheyeMappingArg> for FisheyeMapping {
+    fn from(arg: FisheyeMappingArg) -> Self {
+        match arg {
+            FisheyeMappingArg::Equidistant => FisheyeMapping::Equidistant,
+            FisheyeMappingArg::Equisolid => FisheyeMapping::Equisolid,
+        }
+    }
+}
+
+/// Where a scene places its camera, and how a perspective camera frames it
+struct CameraView {
+    look_from: Vec3,
+    look_at: Vec3,
+    upward: Vec3,
+    vfov: f32,
+    aspect_ratio: f32,
+    aperture: f32,
+    focal_dist: f32,
+}
+
+impl CameraArgs {
+    /// Rejects combinations of options that depend on the values given, which
+    /// clap can't check on its own
+    fn check(&self) -> Result<(), String> {
+        if self.lens.is_some() && !matches!(self.projection, ProjectionArg::Perspective) {
+            let projection = self.projection.to_possible_value().unwrap();
+            return Err(format!(
+                "the argument '--lens <PATH>' cannot be used with '--projection {}'",
+                projection.get_name()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Refocuses the camera once the scene's objects are in place
+    fn focus(
+        &self,
+        camera: Camera,
+        objects: &HittableList,
+        subject: Vec3,
+        height: u32,
+        width: u32,
+    ) -> Camera {
+        if self.focus_on_subject {
+            return camera.focus_on(subject);
+        }
+        if !self.autofocus {
+            return camera;
+        }
+
+        let (x, y) = match self.focus_pixel.as_deref() {
+            Some(&[column, row]) => {
+                let x = (column as f32 + 0.5) / width as f32;
+                let y = 1.0 - (row as f32 + 0.5) / height as f32;
+                match self.stereo {
+                    // Focus on what that eye sees at the pixel
+                    Some(layout) => {
+                        let (x, y, _) = StereoLayout::from(layout).position_in_eye(x, y);
+                        (x, y)
+                    }
+                    None => (x, y),
+                }
+            }
+            _ => (0.5, 0.5),
+        };
+        camera.autofocus(objects, x, y)
+    }
+
+    fn build(&self, mut view: CameraView, objects: &HittableList) -> Result<Camera, String> {
+        // Each eye gets half of the frame
+        match self.stereo {
+            Some(StereoLayoutArg::SideBySide) => view.aspect_ratio /= 2.0,
+            Some(StereoLayoutArg::TopBottom) => view.aspect_ratio *= 2.0,
+            None => {}
+        }
+
+        if let (true, Some(bounds)) = (self.frame, objects.bounding_box()) {
+            let direction = match self.frame_direction.as_deref() {
+                Some(&[x, y, z]) => Vec3(x, y, z),
+                _ => view.look_at - view.look_from,
+            };
+            let (look_from, look_at) = frame_bounds(
+                &bounds,
+                direction,
+                view.upward,
+                view.vfov,
+                view.aspect_ratio,
+                self.frame_margin,
+            )
+            .ok_or("the frame direction must be non-zero and not parallel to the camera's up direction")?;
+            view.look_from = look_from;
+            view.look_at = look_at;
+            view.focal_dist = (look_at - look_from).magnitude();
+        }
+
+        let physical = PhysicalCamera {
+            f_number: self.f_number,
+            focal_length: self.focal_length,
+            sensor_width: self.sensor_width,
+            shutter_speed: self.shutter_speed,
+            iso: self.iso,
+            metres_per_unit: self.metres_per_unit,
+        };
+
+        let camera = match self.projection {
+            ProjectionArg::Perspective if self.lens.is_some() => Camera::realistic(
+                view.look_from,
+                view.look_at,
+                view.upward,
+                view.focal_dist,
+                self.lens.clone().unwrap(),
+                (self.sensor_width, self.sensor_width / view.aspect_ratio),
+                self.metres_per_unit,
+            ),
+            ProjectionArg::Perspective if self.physical => Camera::physical(
+                view.look_from,
+                view.look_at,
+                view.upward,
+                view.aspect_ratio,
+                view.focal_dist,
+                &physical,
+            ),
+            ProjectionArg::Perspective => Camera::new(
+                view.look_from,
+                view.look_at,
+                view.upward,
+                view.vfov,
+                view.aspect_ratio,
+                view.aperture,
+                view.focal_dist,
+            ),
+            ProjectionArg::Orthographic => {
+                let view_width = self.view_width.unwrap_or_else(|| {
+                    2.0 * view.focal_dist * (view.vfov / 2.0).tan() * view.aspect_ratio
+                });
+                Camera::orthographic(
+                    view.look_from,
+                    view.look_at,
+                    view.upward,
+                    view_width,
+                    view.aspect_ratio,
+                )
+            }
+            ProjectionArg::Equirectangular => {
+                Camera::equirectangular(view.look_from, view.look_at, view.upward)
+            }
+            ProjectionArg::Fisheye => Camera::fisheye(
+                view.look_from,
+                view.look_at,
+                view.upward,
+                radians(self.fisheye_fov),
+                self.fisheye_mapping.into(),
+                view.aspect_ratio,
+            ),
+            ProjectionArg::Cubemap => Camera::cubemap(view.look_from, view.look_at, view.upward),
+        };
+        let camera = if self.physical {
+            camera.with_exposure(physical.exposure())
+        } else {
+            camera
+        };
+
+        let camera = match self.stereo {
+            Some(layout) => {
+                let convergence_distance = self.convergence.unwrap_or(view.focal_dist);
+                camera.with_stereo(Stereo {
+                    eye_separation: self.eye_separation.unwrap_or(convergence_distance / 30.0),
+                    convergence_distance,
+                    layout: layout.into(),
+                })
+            }
+            None => camera,
+        };
+
+        let aperture = match (&self.aperture_mask, self.aperture_blades) {
+            (Some(mask), _) => ApertureShape::Mask(mask.clone()),
+            (None, Some(blades)) => ApertureShape::polygon(blades, radians(self.aperture_rotation))
+                .ok_or("an aperture needs at least 3 blades")?,
+            (None, None) => ApertureShape::Circle,
+        };
+
+        Ok(camera
+            .with_aperture(aperture)
+            .with_cats_eye(self.cats_eye.unwrap_or(0.0)))
+    }
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -40,27 +478,203 @@ enum SceneArg {
     Spheres,
     Quads,
     CornellBox,
+    /// Two rows of objects showing off the less common materials
+    Materials,
 }
 
-fn main() -> Result<(), ImageError> {
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum IntegratorArg {
+    /// Unidirectional path tracing
+    Path,
+    /// Unidirectional path tracing with spectra of light instead of RGB
+    Spectral,
+    /// Bidirectional path tracing
+    Bdpt,
+    /// Stochastic progressive photon mapping, `--samples` sets the number of iterations
+    Sppm,
+    /// Metropolis light transport, `--samples` sets the number of mutations per pixel
+    Mlt,
+    /// Surface normals at the first hit
+    Normals,
+    /// Distance to the first hit
+    Depth,
+    /// Base colour of the surface at the first hit
+    Albedo,
+    /// Ambient occlusion at the first hit
+    Ao,
+    /// Number of bounces each path takes
+    Bounces,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum FilterArg {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum AovArg {
+    Depth,
+    Normal,
+    Albedo,
+    Uv,
+    ObjectId,
+    MaterialId,
+    Direct,
+    Indirect,
+    Emission,
+    Variance,
+}
+
+impl From<AovArg> for Aov {
+    fn from(arg: AovArg) -> Self {
+        match arg {
+            AovArg::Depth => Aov::Depth,
+            AovArg::Normal => Aov::Normal,
+            AovArg::Albedo => Aov::Albedo,
+            AovArg::Uv => Aov::Uv,
+            AovArg::ObjectId => Aov::ObjectId,
+            AovArg::MaterialId => Aov::MaterialId,
+            AovArg::Direct => Aov::Direct,
+            AovArg::Indirect => Aov::Indirect,
+            AovArg::Emission => Aov::Emission,
+            AovArg::Variance => Aov::Variance,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum AovOutputArg {
+    /// One EXR file per pass, named `output.<pass>.exr`
+    Separate,
+    /// A single `output.exr` with the image and every pass as layers
+    Multilayer,
+}
+
+/// Parses a filter radius, which must be positive for samples to count
+fn parse_filter_radius(value: &str) -> Result<f32, String> {
+    let radius: f32 = value.parse().map_err(|error| format!("{error}"))?;
+    if radius > 0.0 && radius.is_finite() {
+        Ok(radius)
+    } else {
+        Err("the filter radius must be positive".to_string())
+    }
+}
+
+impl From<FilterArg> for FilterKind {
+    fn from(arg: FilterArg) -> Self {
+        match arg {
+            FilterArg::Box => FilterKind::Box,
+            FilterArg::Tent => FilterKind::Tent,
+            FilterArg::Gaussian => FilterKind::Gaussian,
+            FilterArg::Mitchell => FilterKind::Mitchell,
+            FilterArg::Lanczos => FilterKind::Lanczos,
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    if let Err(message) = args.camera.check() {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, message)
+            .exit();
+    }
 
     let start = std::time::Instant::now();
     let scene = match args.scene {
-        SceneArg::Quads => build_scene_quads(args.height, args.width),
-        SceneArg::Spheres => build_scene_spheres(args.height, args.width),
-        SceneArg::CornellBox => build_cornell_box(args.height, args.width),
+        SceneArg::Quads => build_scene_quads(args.height, args.width, &args.camera),
+        SceneArg::Spheres => build_scene_spheres(args.height, args.width, &args.camera),
+        SceneArg::CornellBox => build_cornell_box(args.height, args.width, &args.camera),
+        SceneArg::Materials => build_scene_materials(args.height, args.width, &args.camera),
+    }
+    .unwrap_or_else(|message| {
+        Args::command()
+            .error(ErrorKind::ValueValidation, message)
+            .exit()
+    });
+    let filter_kind = FilterKind::from(args.filter);
+    let mut settings = RenderSettings {
+        num_samples: args.samples,
+        depth: args.depth,
+        filter: Filter::new(
+            filter_kind,
+            args.filter_radius
+                .unwrap_or_else(|| filter_kind.default_radius()),
+        )
+        .expect("filter radii are checked when parsing arguments"),
+        aovs: args.aov.iter().map(|&aov| Aov::from(aov)).collect(),
+        indirect_clamp: args.clamp_indirect,
+        russian_roulette: args.russian_roulette,
+        integrator: match args.integrator {
+            IntegratorArg::Path => Box::new(PathTracer),
+            IntegratorArg::Spectral => Box::new(SpectralPathTracer),
+            IntegratorArg::Bdpt => Box::new(Bidirectional),
+            IntegratorArg::Sppm => Box::new(ProgressivePhotonMapping {
+                photons_per_iteration: args.photons.unwrap_or(args.width * args.height),
+                initial_radius: args.photon_radius,
+            }),
+            IntegratorArg::Mlt => Box::new(Metropolis {
+                large_step_probability: args.large_step_probability,
+                mutation_size: args.mutation_size,
+            }),
+            IntegratorArg::Normals => Box::new(Normals),
+            IntegratorArg::Depth => Box::new(Depth {
+                max_distance: args.max_distance,
+            }),
+            IntegratorArg::Albedo => Box::new(Albedo),
+            IntegratorArg::Ao => Box::new(AmbientOcclusion {
+                samples: args.ao_samples,
+                max_distance: args.max_distance,
+            }),
+            IntegratorArg::Bounces => Box::new(BounceCount),
+        },
     };
-    let image = scene.render(args.height, args.width, args.samples, args.depth);
+    // Only the passes asked for are written out, not the denoiser's guides
+    let requested_aovs = settings.aovs.clone();
+    if args.denoise {
+        for guide in [Aov::Albedo, Aov::Normal, Aov::Depth] {
+            if !settings.aovs.contains(&guide) {
+                settings.aovs.push(guide);
+            }
+        }
+    }
+
+    let mut output = scene.render(args.height, args.width, &settings);
+    if let Some(threshold) = args.reject_fireflies {
+        output.beauty = reject_fireflies(&output.beauty, args.width, args.height, threshold);
+    }
+    if args.denoise {
+        output.beauty = output
+            .denoised(&DenoiseSettings::default())
+            .ok_or("the integrator didn't render the passes needed to denoise")?;
+        output.aovs.retain(|(aov, _)| requested_aovs.contains(aov));
+    }
     println!(
         "Generated image in {:.2} seconds",
         start.elapsed().as_secs_f32()
     );
 
-    write_image(&image, args.height, "output.png")
+    write_image(&output.beauty, args.height, "output.png")?;
+
+    if !output.aovs.is_empty() {
+        match args.aov_output {
+            AovOutputArg::Separate => {
+                for (aov, _) in &output.aovs {
+                    output.write_aov_exr(*aov, &format!("output.{}.exr", aov.name()))?;
+                }
+            }
+            AovOutputArg::Multilayer => output.write_exr("output.exr")?,
+        }
+    }
+
+    Ok(())
 }
 
-fn build_scene_spheres(height: u32, width: u32) -> Scene {
+fn build_scene_spheres(height: u32, width: u32, camera_args: &CameraArgs) -> Result<Scene, String> {
     let look_from = Vec3(13.0, 2.0, 3.0);
     let look_at = Vec3(0.0, 0.0, 0.0);
     let upward = Vec3(0.0, 1.0, 0.0);
@@ -69,7 +683,7 @@ fn build_scene_spheres(height: u32, width: u32) -> Scene {
     let aperture = 0.1;
     let focal_dist = 10.0;
 
-    let camera = Camera::new(
+    let view = CameraView {
         look_from,
         look_at,
         upward,
@@ -77,7 +691,7 @@ fn build_scene_spheres(height: u32, width: u32) -> Scene {
         aspect_ratio,
         aperture,
         focal_dist,
-    );
+    };
 
     let mut objects = HittableList::new();
 
@@ -104,7 +718,7 @@ fn build_scene_spheres(height: u32, width: u32) -> Scene {
                 objects.push_sphere(Sphere {
                     center,
                     radius: 0.2,
-                    material: random_material(),
+                    material: random_material(&mut rng),
                 });
             }
         }
@@ -130,10 +744,15 @@ fn build_scene_spheres(height: u32, width: u32) -> Scene {
         objects.push_sphere(s);
     }
 
-    Scene { camera, objects }
+    // The glass sphere in the middle
+    let subject = Vec3(0.0, 1.0, 0.0);
+    let camera = camera_args.build(view, &objects)?;
+    let camera = camera_args.focus(camera, &objects, subject, height, width);
+
+    Ok(Scene { camera, objects })
 }
 
-fn build_scene_quads(height: u32, width: u32) -> Scene {
+fn build_scene_quads(height: u32, width: u32, camera_args: &CameraArgs) -> Result<Scene, String> {
     let look_from = Vec3(0.0, 0.0, 9.0);
     let look_at = Vec3(0.0, 0.0, 0.0);
     let upward = Vec3(0.0, 1.0, 0.0);
@@ -143,7 +762,7 @@ fn build_scene_quads(height: u32, width: u32) -> Scene {
     let aperture = 0.1;
     let focal_dist = 10.0;
 
-    let camera = Camera::new(
+    let view = CameraView {
         look_from,
         look_at,
         upward,
@@ -151,7 +770,7 @@ fn build_scene_quads(height: u32, width: u32) -> Scene {
         aspect_ratio,
         aperture,
         focal_dist,
-    );
+    };
 
     let mut objects = HittableList::new();
 
@@ -195,10 +814,15 @@ fn build_scene_quads(height: u32, width: u32) -> Scene {
         lower_teal,
     ));
 
-    Scene { camera, objects }
+    // The centre of the back wall
+    let subject = Vec3(0.0, 0.0, 0.0);
+    let camera = camera_args.build(view, &objects)?;
+    let camera = camera_args.focus(camera, &objects, subject, height, width);
+
+    Ok(Scene { camera, objects })
 }
 
-fn build_cornell_box(height: u32, width: u32) -> Scene {
+fn build_cornell_box(height: u32, width: u32, camera_args: &CameraArgs) -> Result<Scene, String> {
     let look_from = Vec3(278.0, 278.0, -800.0);
     let look_at = Vec3(278.0, 278.0, 0.0);
     let upward = Vec3(0.0, 1.0, 0.0);
@@ -208,7 +832,7 @@ fn build_cornell_box(height: u32, width: u32) -> Scene {
     let aperture = 0.1;
     let focal_dist = (look_at - look_from).magnitude();
 
-    let camera = Camera::new(
+    let view = CameraView {
         look_from,
         look_at,
         upward,
@@ -216,7 +840,7 @@ fn build_cornell_box(height: u32, width: u32) -> Scene {
         aspect_ratio,
         aperture,
         focal_dist,
-    );
+    };
 
     let mut objects = HittableList::new();
 
@@ -246,13 +870,13 @@ fn build_cornell_box(height: u32, width: u32) -> Scene {
         Vec3(0.0, 0.0, 0.0),
         Vec3(555.0, 0.0, 0.0),
         Vec3(0.0, 0.0, 555.0),
-        white,
+        white.clone(),
     ));
     objects.push_quad(Quad::new(
         Vec3(555.0, 555.0, 555.0),
         Vec3(-555.0, 0.0, 0.0),
         Vec3(0.0, 0.0, -555.0),
-        white,
+        white.clone(),
     ));
     objects.push_quad(Quad::new(
         Vec3(0.0, 0.0, 555.0),
@@ -261,12 +885,12 @@ fn build_cornell_box(height: u32, width: u32) -> Scene {
         Material::Metal(Vec3(1.0, 0.7, 0.8), 0.3),
     ));
 
-    for s in box_sides(Vec3(130.0, 0.0, 65.0), Vec3(295.0, 165.0, 230.0), white) {
+    for s in box_sides(Vec3(130.0, 0.0, 65.0), Vec3(295.0, 165.0, 230.0), &white) {
         objects.push_quad(s)
     }
 
     // Taller box
-    for s in box_sides(Vec3(265.0, 0.0, 295.0), Vec3(430.0, 330.0, 460.0), white) {
+    for s in box_sides(Vec3(265.0, 0.0, 295.0), Vec3(430.0, 330.0, 460.0), &white) {
         objects.push_quad(s)
     }
 
@@ -276,7 +900,148 @@ fn build_cornell_box(height: u32, width: u32) -> Scene {
         material: Material::Metal(Vec3(0.3, 0.2, 0.8), 0.3),
     });
 
-    Scene { camera, objects }
+    // The metal sphere at the front
+    let subject = Vec3(400.0, 50.0, 80.0);
+    let camera = camera_args.build(view, &objects)?;
+    let camera = camera_args.focus(camera, &objects, subject, height, width);
+
+    Ok(Scene { camera, objects })
+}
+
+fn build_scene_materials(
+    height: u32,
+    width: u32,
+    camera_args: &CameraArgs,
+) -> Result<Scene, String> {
+    // High enough to see the back row over the front one
+    let look_from = Vec3(0.0, 7.0, 11.0);
+    let look_at = Vec3(0.0, 0.8, 0.0);
+    let upward = Vec3(0.0, 1.0, 0.0);
+
+    let aspect_ratio = (width as f32) / (height as f32);
+    let vfov = radians(40.0);
+    let aperture = 0.0;
+    let focal_dist = (look_at - look_from).magnitude();
+
+    let view = CameraView {
+        look_from,
+        look_at,
+        upward,
+        vfov,
+        aspect_ratio,
+        aperture,
+        focal_dist,
+    };
+
+    let mut objects = HittableList::new();
+
+    // Floor, and a checkered wall behind to see refraction against
+    objects.push_quad(Quad::new(
+        Vec3(-10.0, 0.0, 10.0),
+        Vec3(20.0, 0.0, 0.0),
+        Vec3(0.0, 0.0, -14.0),
+        Material::Lambertian(Texture::Constant(Vec3(0.5, 0.5, 0.5))),
+    ));
+    objects.push_quad(Quad::new(
+        Vec3(-10.0, 0.0, -4.0),
+        Vec3(20.0, 0.0, 0.0),
+        Vec3(0.0, 10.0, 0.0),
+        Material::Lambertian(Texture::Checkered(Vec3(0.2, 0.2, 0.2), Vec3(0.8, 0.8, 0.8))),
+    ));
+    objects.push_quad(Quad::new(
+        Vec3(-3.0, 6.0, -2.0),
+        Vec3(6.0, 0.0, 0.0),
+        Vec3(0.0, 0.0, 4.0),
+        Material::Light(Vec3(4.0, 4.0, 4.0)),
+    ));
+
+    // Front row, from left to right
+    objects.push_sphere(Sphere {
+        center: Vec3(-3.0, 0.8, 1.5),
+        radius: 0.8,
+        material: Material::Dispersive(Dispersion::DIAMOND),
+    });
+    let tinted =
+        Material::tinted_glass(1.5, Vec3(0.3, 0.6, 0.9), 1.0).expect("the distance is positive");
+    for side in box_sides(Vec3(-1.7, 0.0, 0.8), Vec3(-0.3, 1.4, 2.2), &tinted) {
+        objects.push_quad(side);
+    }
+    // Soap-bubble colours on glass, and a blue-purple tint on gold
+    objects.push_sphere(Sphere {
+        center: Vec3(1.0, 0.8, 1.5),
+        radius: 0.8,
+        material: Material::CoatedDielectric {
+            ior: 1.5,
+            film: ThinFilm {
+                thickness: 400.0,
+                ior: 1.33,
+            },
+        },
+    });
+    objects.push_sphere(Sphere {
+        center: Vec3(3.0, 0.8, 1.5),
+        radius: 0.8,
+        material: Material::CoatedMetal {
+            albedo: Vec3(1.0, 0.78, 0.34),
+            fuzz: 0.05,
+            film: ThinFilm {
+                thickness: 250.0,
+                ior: 2.0,
+            },
+        },
+    });
+
+    // Back row, from left to right. Translucent wax glows warmly where light
+    // scatters out.
+    objects.push_sphere(Sphere {
+        center: Vec3(-3.0, 0.8, -2.0),
+        radius: 0.8,
+        material: Material::Subsurface {
+            albedo: Vec3(0.95, 0.85, 0.7),
+            mean_free_path: Vec3(0.3, 0.15, 0.1),
+            ior: 1.4,
+        },
+    });
+    // Red paint under a clear, slightly amber lacquer
+    objects.push_sphere(Sphere {
+        center: Vec3(-1.0, 0.8, -2.0),
+        radius: 0.8,
+        material: Material::Layered {
+            ior: 1.5,
+            roughness: 0.0,
+            absorption: Vec3(0.5, 1.0, 2.0),
+            thickness: 0.1,
+            base: Box::new(Material::Lambertian(Texture::Constant(Vec3(
+                0.7, 0.05, 0.05,
+            )))),
+        },
+    });
+    // Gold and blue paint in a checkered pattern, and a dim orange glow from
+    // a surface that also reflects
+    objects.push_sphere(Sphere {
+        center: Vec3(1.0, 0.8, -2.0),
+        radius: 0.8,
+        material: Material::masked(
+            Material::Metal(Vec3(1.0, 0.78, 0.34), 0.1),
+            Material::Lambertian(Texture::Constant(Vec3(0.1, 0.2, 0.6))),
+            Texture::Checkered(Vec3(0.0, 0.0, 0.0), Vec3(1.0, 1.0, 1.0)),
+        ),
+    });
+    objects.push_sphere(Sphere {
+        center: Vec3(3.0, 0.8, -2.0),
+        radius: 0.8,
+        material: Material::combine(
+            Material::Light(Vec3(0.6, 0.25, 0.05)),
+            Material::Lambertian(Texture::Constant(Vec3(0.5, 0.5, 0.5))),
+        ),
+    });
+
+    // The middle of the two rows
+    let subject = Vec3(0.0, 0.8, -0.25);
+    let camera = camera_args.build(view, &objects)?;
+    let camera = camera_args.focus(camera, &objects, subject, height, width);
+
+    Ok(Scene { camera, objects })
 }
 
 fn generate_lights() -> Vec<Sphere> {
@@ -294,20 +1059,19 @@ fn generate_lights() -> Vec<Sphere> {
     lights
 }
 
-fn random_material() -> Material {
-    let mut rng = rand::thread_rng();
+fn random_material(rng: &mut impl Rng) -> Material {
     let choice = rng.gen::<f32>();
 
     if choice < 0.5 {
-        Material::Lambertian(Texture::Constant(Vec3::random() * Vec3::random()))
+        Material::Lambertian(Texture::Constant(Vec3::random(rng) * Vec3::random(rng)))
     } else if choice < 0.75 {
-        Material::Metal(0.5 * (Vec3::random() + 1.0), 0.5 * rng.gen::<f32>())
+        Material::Metal(0.5 * (Vec3::random(rng) + 1.0), 0.5 * rng.gen::<f32>())
     } else {
         Material::Dielectric(1.5)
     }
 }
 
-fn write_image(image: &Vec<Vec3>, height: u32, path: &str) -> Result<(), ImageError> {
+fn write_image(image: &[Vec3], height: u32, path: &str) -> Result<(), ImageError> {
     let width = image.len() as u32 / height;
     let mut buffer = Vec::with_capacity((height * width * 3) as usize);
 
@@ -320,8 +1084,8 @@ fn write_image(image: &Vec<Vec3>, height: u32, path: &str) -> Result<(), ImageEr
     image::save_buffer_with_format(
         path,
         buffer.as_slice(),
-        width as u32,
-        height as u32,
+        width,
+        height,
         image::ColorType::Rgb8,
         image::ImageFormat::Png,
     )
@@ -336,7 +1100,7 @@ fn radians(deg: f32) -> f32 {
 }
 
 /// Returns the 3D box (six sides) that contains the opposite vertices a and b
-fn box_sides(a: Vec3, b: Vec3, mat: Material) -> Vec<Quad> {
+fn box_sides(a: Vec3, b: Vec3, mat: &Material) -> Vec<Quad> {
     // Construct the two opposite vertices with the minimum and maximum coordinates.
     let min = Vec3(a.0.min(b.0), a.1.min(b.1), a.2.min(b.2));
     let max = Vec3(a.0.max(b.0), a.1.max(b.1), a.2.max(b.2));
@@ -346,11 +1110,11 @@ fn box_sides(a: Vec3, b: Vec3, mat: Material) -> Vec<Quad> {
     let dz = Vec3(0.0, 0.0, max.2 - min.2);
 
     vec![
-        Quad::new(Vec3(min.0, min.1, max.2), dx, dy, mat), // front
-        Quad::new(Vec3(max.0, min.1, max.2), -dz, dy, mat), // right
-        Quad::new(Vec3(max.0, min.1, min.2), -dx, dy, mat), // back
-        Quad::new(Vec3(min.0, min.1, min.2), dz, dy, mat), // left
-        Quad::new(Vec3(min.0, max.1, max.2), dx, -dz, mat), // top
-        Quad::new(Vec3(min.0, min.1, min.2), dx, dz, mat), // bottom
+        Quad::new(Vec3(min.0, min.1, max.2), dx, dy, mat.clone()), // front
+        Quad::new(Vec3(max.0, min.1, max.2), -dz, dy, mat.clone()), // right
+        Quad::new(Vec3(max.0, min.1, min.2), -dx, dy, mat.clone()), // back
+        Quad::new(Vec3(min.0, min.1, min.2), dz, dy, mat.clone()), // left
+        Quad::new(Vec3(min.0, max.1, max.2), dx, -dz, mat.clone()), // top
+        Quad::new(Vec3(min.0, min.1, min.2), dx, dz, mat.clone()), // bottom
     ]
 }
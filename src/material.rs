@@ -2,30 +2,193 @@ use rand::Rng;
 
 use crate::hittable::HitRecord;
 use crate::ray::Ray;
+use crate::spectral::{SampledSpectrum, SampledWavelengths};
 use crate::texture::Texture;
+use crate::thin_film::{Substrate, ThinFilm};
 use crate::vec3::Vec3;
 
-#[derive(Copy, Clone)]
+#[derive(Clone, PartialEq)]
 pub enum Material {
     Lambertian(Texture),
     Metal(Vec3, f32),
     Dielectric(f32),
+    /// Glass whose refractive index varies with the wavelength, splitting
+    /// white light into a rainbow with `--integrator spectral`. Other
+    /// integrators use its index for yellow light.
+    Dispersive(Dispersion),
+    /// Glass that absorbs light as it passes through, more so the further it
+    /// travels. `absorption` is the absorption coefficient for each colour,
+    /// so that light crossing a distance `d` is scaled by `exp(-absorption *
+    /// d)` and thicker glass looks darker.
+    AbsorbingDielectric {
+        ior: f32,
+        absorption: Vec3,
+    },
+    /// Glass under a thin transparent film, which reflects some colours more
+    /// strongly than others depending on the viewing angle
+    CoatedDielectric {
+        ior: f32,
+        film: ThinFilm,
+    },
+    /// Metal under a thin transparent film
+    CoatedMetal {
+        albedo: Vec3,
+        fuzz: f32,
+        film: ThinFilm,
+    },
+    /// A translucent material like wax, skin or marble, which light enters
+    /// through a smooth surface with refractive index `ior` and scatters
+    /// around inside. Only the path tracers follow light beneath the surface;
+    /// other integrators render it as clear glass.
+    Subsurface {
+        /// Fraction of light that survives each scattering event inside
+        albedo: Vec3,
+        /// Average distance light travels inside between scattering events
+        mean_free_path: Vec3,
+        ior: f32,
+    },
+    /// A clear coat, like varnish or the lacquer on car paint, over another
+    /// material. Light either reflects off the coat, or passes through it to
+    /// scatter from `base`, losing some to absorption on the way in and out
+    /// again. Treated as specular even over a diffuse base, so BDPT doesn't
+    /// connect subpaths to it and SPPM doesn't gather photons on it; both
+    /// still render it correctly, but with more noise.
+    Layered {
+        /// Refractive index of the coat
+        ior: f32,
+        /// How blurry reflections off the coat are, from 0 for a mirror finish
+        roughness: f32,
+        /// Absorption coefficient of the coat for each colour, per unit
+        /// distance, as for `AbsorbingDielectric`
+        absorption: Vec3,
+        thickness: f32,
+        base: Box<Material>,
+    },
+    /// Either of two materials, picked at random each time light scatters.
+    /// `mask` is the fraction of light that scatters from `second` at each
+    /// point, averaged over its colours. A mix with any specular component is
+    /// treated as specular, so BDPT doesn't connect to its diffuse part and
+    /// SPPM doesn't gather photons on it.
+    Mix {
+        first: Box<Material>,
+        second: Box<Material>,
+        mask: Texture,
+    },
+    /// Two materials together, emitting and reflecting the light of both,
+    /// such as a glowing surface that also reflects. Treated as specular if
+    /// either part is, as for `Mix`.
+    Add(Box<Material>, Box<Material>),
     Light(Vec3),
 }
 
+/// Wavelengths in nanometres standing in for red, green and blue, when
+/// scattering depends on the wavelength
+const RGB_WAVELENGTHS: [f32; 3] = [630.0, 532.0, 465.0];
+
+/// How a material's refractive index varies with the wavelength of light
+#[derive(Copy, Clone, PartialEq)]
+pub enum Dispersion {
+    /// `a + b / λ²`, with λ in micrometres
+    Cauchy { a: f32, b: f32 },
+    /// `n² = 1 + Σ b λ² / (λ² - c)`, with λ in micrometres
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7, a common crown glass
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+    /// Schott SF11, a dense flint glass with strong dispersion
+    pub const FLINT: Dispersion = Dispersion::Sellmeier {
+        b: [1.737_597, 0.313_747_35, 1.898_781],
+        c: [0.013_188_707, 0.062_306_814, 155.236_3],
+    };
+    /// Diamond, from F. Peter's measurements (Z. Phys. 15, 1923)
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030_625, 0.011_236, 0.0],
+    };
+
+    /// Wavelength of yellow light in nanometres, at which glasses' refractive
+    /// indices are usually quoted
+    const YELLOW: f32 = 587.6;
+
+    /// Refractive index at a wavelength in nanometres
+    pub fn ior(&self, lambda: f32) -> f32 {
+        let micrometres = lambda / 1000.0;
+        let l2 = micrometres * micrometres;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>();
+                n2.sqrt()
+            }
+        }
+    }
+}
+
 impl Material {
-    pub fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
+    /// Glass with refractive index `ior` that lets through `color` of the light
+    /// crossing `distance` inside it. Components of `color` are clamped to
+    /// (0, 1], since glass can't add light. `None` unless `distance` is
+    /// positive and finite.
+    pub fn tinted_glass(ior: f32, color: Vec3, distance: f32) -> Option<Self> {
+        (distance > 0.0 && distance.is_finite()).then(|| Material::AbsorbingDielectric {
+            ior,
+            absorption: color.map(|c| -c.clamp(1e-6, 1.0).ln() / distance),
+        })
+    }
+
+    /// `first` blended with `second`, with `weight` of the light scattering
+    /// from `second` everywhere
+    pub fn mix(first: Material, second: Material, weight: f32) -> Self {
+        Self::masked(
+            first,
+            second,
+            Texture::Constant(Vec3(weight, weight, weight)),
+        )
+    }
+
+    /// `first` blended with `second`, with `mask` of the light scattering from
+    /// `second` at each point
+    pub fn masked(first: Material, second: Material, mask: Texture) -> Self {
+        Material::Mix {
+            first: Box::new(first),
+            second: Box::new(second),
+            mask,
+        }
+    }
+
+    /// The light of `first` and `second` added together
+    pub fn combine(first: Material, second: Material) -> Self {
+        Material::Add(Box::new(first), Box::new(second))
+    }
+
+    pub fn scatter<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut R,
+    ) -> Option<(Ray, Vec3)> {
         match self {
             Material::Lambertian(texture) => {
+                // Offsetting the normal by a point on the unit sphere gives a
+                // cosine-weighted direction, matching `pdf`
+                let mut dir = hit_record.normal + Vec3::random_unit_vector(rng);
+                if dir.dot(dir) < 1e-8 {
+                    dir = hit_record.normal;
+                }
                 let ray = Ray {
                     origin: hit_record.intersection,
-                    dir: hit_record.normal + Vec3::random_in_unit_sphere(),
+                    dir,
                 };
                 Some((ray, texture.value_at(hit_record.intersection)))
             }
             Material::Metal(albedo, fuzz) => {
                 let reflected = reflect(ray.dir.normalize(), hit_record.normal);
-                let dir = reflected + *fuzz * Vec3::random_in_unit_sphere();
+                let dir = reflected + *fuzz * Vec3::random_in_unit_sphere(rng);
                 if dir.dot(hit_record.normal) > 0.0 {
                     let ray = Ray {
                         origin: hit_record.intersection,
@@ -37,50 +200,507 @@ impl Material {
                     None
                 }
             }
-            Material::Dielectric(ref_idx) => {
-                let incident = ray.dir.normalize();
-                let ray_normal_dot = incident.dot(hit_record.normal);
+            Material::Dielectric(ref_idx) => Some((
+                scatter_dielectric(ray, hit_record, *ref_idx, rng),
+                Vec3(1.0, 1.0, 1.0),
+            )),
+            Material::Dispersive(dispersion) => Some((
+                scatter_dielectric(ray, hit_record, dispersion.ior(Dispersion::YELLOW), rng),
+                Vec3(1.0, 1.0, 1.0),
+            )),
+            Material::AbsorbingDielectric { ior, absorption } => Some((
+                scatter_dielectric(ray, hit_record, *ior, rng),
+                transmittance(ray, hit_record, *absorption),
+            )),
+            Material::CoatedDielectric { ior, film } => {
+                let (ray, [r, g, b]) =
+                    scatter_coated_dielectric(ray, hit_record, *ior, film, RGB_WAVELENGTHS, rng);
+                Some((ray, Vec3(r, g, b)))
+            }
+            Material::CoatedMetal { albedo, fuzz, film } => {
+                let base = [albedo.0, albedo.1, albedo.2];
+                let (ray, [r, g, b]) =
+                    scatter_coated_metal(ray, hit_record, *fuzz, film, RGB_WAVELENGTHS, base, rng)?;
+                Some((ray, Vec3(r, g, b)))
+            }
+            Material::Subsurface { ior, .. } => Some((
+                scatter_dielectric(ray, hit_record, *ior, rng),
+                Vec3(1.0, 1.0, 1.0),
+            )),
+            Material::Layered {
+                ior,
+                roughness,
+                absorption,
+                thickness,
+                base,
+            } => match pick_layer(ray, hit_record, *ior, *roughness, rng)? {
+                Layer::Coat(ray) => Some((ray, Vec3(1.0, 1.0, 1.0))),
+                Layer::Base => {
+                    let (scattered, attenuation) = base.scatter(ray, hit_record, rng)?;
+                    let coat = coat_transmittance(
+                        ray,
+                        &scattered,
+                        hit_record,
+                        *ior,
+                        *thickness * *absorption,
+                    );
+                    Some((scattered, attenuation * coat))
+                }
+            },
+            Material::Mix { .. } | Material::Add(..) => {
+                let (component, weight) = self.pick_component(hit_record.intersection, rng)?;
+                let (ray, attenuation) = component.scatter(ray, hit_record, rng)?;
+                Some((ray, weight * attenuation))
+            }
+            Material::Light(_) => None,
+        }
+    }
 
-                let (outward_normal, refract_ratio, cos_i) = if ray_normal_dot < 0.0 {
-                    // Ray is coming from outside the surface
-                    (hit_record.normal, 1.0 / ref_idx, -ray_normal_dot)
-                } else {
-                    (-hit_record.normal, *ref_idx, ref_idx * ray_normal_dot)
-                };
+    /// Like `scatter`, with the attenuation at each of the path's wavelengths
+    pub(crate) fn scatter_spectral<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        wavelengths: &mut SampledWavelengths,
+        rng: &mut R,
+    ) -> Option<(Ray, SampledSpectrum)> {
+        match self {
+            Material::Dispersive(dispersion) => {
+                // Each wavelength bends by a different amount, so only the hero
+                // can follow the refracted ray
+                wavelengths.terminate_secondary();
+                let ior = dispersion.ior(wavelengths.hero());
+                let ray = scatter_dielectric(ray, hit_record, ior, rng);
+                Some((ray, wavelengths.upsample(Vec3(1.0, 1.0, 1.0))))
+            }
+            Material::CoatedDielectric { ior, film } => {
+                let (ray, values) =
+                    scatter_coated_dielectric(ray, hit_record, *ior, film, wavelengths.lambda, rng);
+                Some((ray, SampledSpectrum(values)))
+            }
+            Material::CoatedMetal { albedo, fuzz, film } => {
+                let (ray, values) = scatter_coated_metal(
+                    ray,
+                    hit_record,
+                    *fuzz,
+                    film,
+                    wavelengths.lambda,
+                    wavelengths.upsample(*albedo).0,
+                    rng,
+                )?;
+                Some((ray, SampledSpectrum(values)))
+            }
+            Material::Layered {
+                ior,
+                roughness,
+                absorption,
+                thickness,
+                base,
+            } => match pick_layer(ray, hit_record, *ior, *roughness, rng)? {
+                Layer::Coat(ray) => Some((ray, wavelengths.upsample(Vec3(1.0, 1.0, 1.0)))),
+                Layer::Base => {
+                    let (scattered, attenuation) =
+                        base.scatter_spectral(ray, hit_record, wavelengths, rng)?;
+                    let coat = coat_transmittance(
+                        ray,
+                        &scattered,
+                        hit_record,
+                        *ior,
+                        *thickness * *absorption,
+                    );
+                    Some((scattered, attenuation * wavelengths.upsample(coat)))
+                }
+            },
+            Material::Mix { .. } | Material::Add(..) => {
+                let (component, weight) = self.pick_component(hit_record.intersection, rng)?;
+                let (ray, attenuation) =
+                    component.scatter_spectral(ray, hit_record, wavelengths, rng)?;
+                Some((ray, weight * attenuation))
+            }
+            _ => {
+                let (ray, attenuation) = self.scatter(ray, hit_record, rng)?;
+                Some((ray, wavelengths.upsample(attenuation)))
+            }
+        }
+    }
 
-                let reflect_prob = schlick(cos_i, *ref_idx);
-                let should_refract = rand::thread_rng().gen::<f32>() > reflect_prob;
+    /// Whether the material gives off light, so that objects made of it are
+    /// sampled as lights
+    pub(crate) fn is_emissive(&self) -> bool {
+        match self {
+            Material::Light(_) => true,
+            Material::Layered { base, .. } => base.is_emissive(),
+            Material::Mix { first, second, .. } | Material::Add(first, second) => {
+                first.is_emissive() || second.is_emissive()
+            }
+            _ => false,
+        }
+    }
 
-                let dir = if should_refract {
-                    refract(incident, outward_normal, refract_ratio)
-                        .unwrap_or_else(|| reflect(incident, outward_normal))
-                } else {
-                    reflect(incident, outward_normal)
-                };
+    /// Whether the material scatters light in a single direction (or close to
+    /// one), so that its BSDF can't usefully be evaluated for an arbitrary pair
+    /// of directions
+    pub(crate) fn is_specular(&self) -> bool {
+        if let Material::Mix { first, second, .. } | Material::Add(first, second) = self {
+            return first.is_specular() || second.is_specular();
+        }
+        matches!(
+            self,
+            Material::Metal(..)
+                | Material::Dielectric(_)
+                | Material::Dispersive(_)
+                | Material::AbsorbingDielectric { .. }
+                | Material::CoatedDielectric { .. }
+                | Material::CoatedMetal { .. }
+                | Material::Subsurface { .. }
+                | Material::Layered { .. }
+        )
+    }
 
-                let ray = Ray {
-                    origin: hit_record.intersection,
-                    dir,
-                };
-                Some((ray, Vec3(1.0, 1.0, 1.0)))
+    /// The BSDF at `point` for light arriving from `wi` and leaving towards
+    /// `wo`, both unit vectors pointing away from the surface. Always zero for
+    /// specular materials.
+    pub(crate) fn eval(&self, point: Vec3, normal: Vec3, wo: Vec3, wi: Vec3) -> Vec3 {
+        match self {
+            Material::Lambertian(texture) if same_side(normal, wo, wi) => {
+                texture.value_at(point) / std::f32::consts::PI
             }
-            Material::Light(_) => None,
+            Material::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                let weight = mask_weight(mask, point);
+                (1.0 - weight) * first.eval(point, normal, wo, wi)
+                    + weight * second.eval(point, normal, wo, wi)
+            }
+            Material::Add(first, second) => {
+                first.eval(point, normal, wo, wi) + second.eval(point, normal, wo, wi)
+            }
+            _ => Vec3::default(),
+        }
+    }
+
+    /// Probability density (per unit solid angle) of `scatter` sending light
+    /// that leaves towards `wo` in the direction `wi`. Always zero for specular
+    /// materials.
+    pub(crate) fn pdf(&self, point: Vec3, normal: Vec3, wo: Vec3, wi: Vec3) -> f32 {
+        match self {
+            Material::Lambertian(_) if same_side(normal, wo, wi) => {
+                normal.dot(wi).abs() / std::f32::consts::PI
+            }
+            Material::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                let weight = mask_weight(mask, point);
+                (1.0 - weight) * first.pdf(point, normal, wo, wi)
+                    + weight * second.pdf(point, normal, wo, wi)
+            }
+            Material::Add(first, second) => {
+                let first_pdf = first.pdf(point, normal, wo, wi);
+                let second_pdf = second.pdf(point, normal, wo, wi);
+                match (first.scatters(), second.scatters()) {
+                    (true, true) => 0.5 * (first_pdf + second_pdf),
+                    (true, false) => first_pdf,
+                    (false, true) => second_pdf,
+                    (false, false) => 0.0,
+                }
+            }
+            _ => 0.0,
         }
     }
 
-    pub fn emit(&self) -> Vec3 {
+    /// Base colour of the surface, independent of lighting
+    pub fn albedo(&self, hit_record: &HitRecord) -> Vec3 {
+        match self {
+            Material::Lambertian(texture) => texture.value_at(hit_record.intersection),
+            Material::Metal(albedo, _)
+            | Material::CoatedMetal { albedo, .. }
+            | Material::Subsurface { albedo, .. } => *albedo,
+            Material::Dielectric(_)
+            | Material::Dispersive(_)
+            | Material::AbsorbingDielectric { .. }
+            | Material::CoatedDielectric { .. } => Vec3(1.0, 1.0, 1.0),
+            Material::Layered { base, .. } => base.albedo(hit_record),
+            Material::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                let weight = mask_weight(mask, hit_record.intersection);
+                (1.0 - weight) * first.albedo(hit_record) + weight * second.albedo(hit_record)
+            }
+            Material::Add(first, second) => {
+                (first.albedo(hit_record) + second.albedo(hit_record)).map(|c| c.min(1.0))
+            }
+            Material::Light(color) => color.map(|c| c.min(1.0)),
+        }
+    }
+
+    /// Light given off at `point` on the surface
+    pub fn emit(&self, point: Vec3) -> Vec3 {
         match self {
             Material::Light(color) => *color,
+            Material::Layered { base, .. } => base.emit(point),
+            Material::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                let weight = mask_weight(mask, point);
+                (1.0 - weight) * first.emit(point) + weight * second.emit(point)
+            }
+            Material::Add(first, second) => first.emit(point) + second.emit(point),
             _ => Vec3(0.0, 0.0, 0.0),
         }
     }
+
+    /// Whether light arriving at the material can scatter from it, rather
+    /// than always being absorbed
+    pub(crate) fn scatters(&self) -> bool {
+        match self {
+            Material::Light(_) => false,
+            Material::Add(first, second) => first.scatters() || second.scatters(),
+            _ => true,
+        }
+    }
+
+    /// For a material combining others, the one to scatter from at `point`,
+    /// picked at random, along with the weight that makes up for the chance of
+    /// picking it. `None` if neither can scatter.
+    fn pick_component<R: Rng + ?Sized>(
+        &self,
+        point: Vec3,
+        rng: &mut R,
+    ) -> Option<(&Material, f32)> {
+        match self {
+            Material::Mix {
+                first,
+                second,
+                mask,
+            } => {
+                if rng.gen::<f32>() < mask_weight(mask, point) {
+                    Some((second, 1.0))
+                } else {
+                    Some((first, 1.0))
+                }
+            }
+            Material::Add(first, second) => match (first.scatters(), second.scatters()) {
+                (true, true) if rng.gen::<bool>() => Some((first, 2.0)),
+                (true, true) => Some((second, 2.0)),
+                (true, false) => Some((first, 1.0)),
+                (false, true) => Some((second, 1.0)),
+                (false, false) => None,
+            },
+            _ => Some((self, 1.0)),
+        }
+    }
+}
+
+/// Fraction of a mix from its second material at `point`
+fn mask_weight(mask: &Texture, point: Vec3) -> f32 {
+    let Vec3(r, g, b) = mask.value_at(point);
+    ((r + g + b) / 3.0).clamp(0.0, 1.0)
+}
+
+/// Reflects or refracts a ray hitting glass with refractive index `ref_idx`,
+/// picking between them with the Fresnel reflectance
+fn scatter_dielectric<R: Rng + ?Sized>(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    ref_idx: f32,
+    rng: &mut R,
+) -> Ray {
+    let incident = ray.dir.normalize();
+    let (outward_normal, cos) = facing_normal(incident, hit_record);
+
+    let (refract_ratio, cos_i) = if hit_record.front_face {
+        // Ray is coming from outside the surface
+        (1.0 / ref_idx, cos)
+    } else {
+        (ref_idx, ref_idx * cos)
+    };
+
+    let reflect_prob = schlick(cos_i, ref_idx);
+    let should_refract = rng.gen::<f32>() > reflect_prob;
+
+    let dir = if should_refract {
+        refract(incident, outward_normal, refract_ratio)
+            .unwrap_or_else(|| reflect(incident, outward_normal))
+    } else {
+        reflect(incident, outward_normal)
+    };
+
+    Ray {
+        origin: hit_record.intersection,
+        dir,
+    }
+}
+
+/// Reflects or refracts a ray hitting coated glass, at each of `lambdas`.
+/// Picks between them with the average reflectance, and weights each
+/// wavelength by its own.
+fn scatter_coated_dielectric<const N: usize, R: Rng + ?Sized>(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    ior: f32,
+    film: &ThinFilm,
+    lambdas: [f32; N],
+    rng: &mut R,
+) -> (Ray, [f32; N]) {
+    let incident = ray.dir.normalize();
+    let (outward_normal, cos_i) = facing_normal(incident, hit_record);
+
+    // The film is on the outside of the object
+    let (outer_ior, inner_ior) = if hit_record.front_face {
+        (1.0, ior)
+    } else {
+        (ior, 1.0)
+    };
+
+    let reflectance = lambdas
+        .map(|lambda| film.reflectance(outer_ior, Substrate::Dielectric(inner_ior), cos_i, lambda));
+    let reflect_prob = reflectance.iter().sum::<f32>() / N as f32;
+
+    let (dir, weights) = match refract(incident, outward_normal, outer_ior / inner_ior) {
+        Some(dir) if rng.gen::<f32>() >= reflect_prob => {
+            (dir, reflectance.map(|r| (1.0 - r) / (1.0 - reflect_prob)))
+        }
+        _ => (
+            reflect(incident, outward_normal),
+            reflectance.map(|r| r / reflect_prob.max(1e-6)),
+        ),
+    };
+
+    let ray = Ray {
+        origin: hit_record.intersection,
+        dir,
+    };
+    (ray, weights)
+}
+
+/// Reflects a ray off coated metal, which reflects `base` of the light at
+/// each of `lambdas` without the coating
+fn scatter_coated_metal<const N: usize, R: Rng + ?Sized>(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    fuzz: f32,
+    film: &ThinFilm,
+    lambdas: [f32; N],
+    base: [f32; N],
+    rng: &mut R,
+) -> Option<(Ray, [f32; N])> {
+    let incident = ray.dir.normalize();
+    let reflected = reflect(incident, hit_record.normal);
+    let dir = reflected + fuzz * Vec3::random_in_unit_sphere(rng);
+    if dir.dot(hit_record.normal) <= 0.0 {
+        // The ray has scattered below the surface
+        return None;
+    }
+
+    let cos_i = (-incident.dot(hit_record.normal)).clamp(0.0, 1.0);
+    let reflectance = std::array::from_fn(|i| {
+        film.reflectance(1.0, Substrate::Conductor(base[i]), cos_i, lambdas[i])
+    });
+    let ray = Ray {
+        origin: hit_record.intersection,
+        dir,
+    };
+    Some((ray, reflectance))
+}
+
+/// The layer of a coated material that light scatters from
+enum Layer {
+    /// Reflected off the coat, in this direction
+    Coat(Ray),
+    /// Passed through the coat to the material beneath
+    Base,
+}
+
+/// Picks whether a ray reflects off a coat with refractive index `ior` or
+/// passes through it, with the Fresnel reflectance. Rays arriving from inside
+/// the object go straight to the base. `None` if a rough reflection scatters
+/// below the surface.
+fn pick_layer<R: Rng + ?Sized>(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    ior: f32,
+    roughness: f32,
+    rng: &mut R,
+) -> Option<Layer> {
+    let incident = ray.dir.normalize();
+    let cos_i = -incident.dot(hit_record.normal);
+    if cos_i <= 0.0 || rng.gen::<f32>() >= schlick(cos_i, ior) {
+        return Some(Layer::Base);
+    }
+
+    let dir = reflect(incident, hit_record.normal) + roughness * Vec3::random_in_unit_sphere(rng);
+    if dir.dot(hit_record.normal) <= 0.0 {
+        return None;
+    }
+    Some(Layer::Coat(Ray {
+        origin: hit_record.intersection,
+        dir,
+    }))
+}
+
+/// Fraction of the light scattered by the base from `ray` into `scattered`
+/// that makes it through the coat, having already passed in through the top
+/// of it. Light is absorbed along its way down through the coat and back up,
+/// and some is reflected back down as it leaves, which is treated as lost so
+/// that the layers never reflect more light than arrives. `optical_depth` is
+/// the coat's absorption times its thickness.
+fn coat_transmittance(
+    ray: &Ray,
+    scattered: &Ray,
+    hit_record: &HitRecord,
+    ior: f32,
+    optical_depth: Vec3,
+) -> Vec3 {
+    let cos_in = ray.dir.normalize().dot(hit_record.normal).abs();
+    let cos_out = scattered.dir.normalize().dot(hit_record.normal).abs();
+    // Cosine of the angle to the normal inside the coat, for light crossing it
+    // at an angle with cosine `cos`
+    let refracted = |cos: f32| (1.0 - (1.0 - cos * cos) / (ior * ior)).max(1e-6).sqrt();
+
+    let path_length = 1.0 / refracted(cos_in) + 1.0 / refracted(cos_out);
+    let absorbed = (-path_length * optical_depth).map(f32::exp);
+    (1.0 - schlick(cos_out, ior)) * absorbed
+}
+
+/// The normal on the side of the surface that `incident` arrives from, along
+/// with the cosine of the angle between them
+fn facing_normal(incident: Vec3, hit_record: &HitRecord) -> (Vec3, f32) {
+    let cos = -incident.dot(hit_record.normal);
+    if cos > 0.0 {
+        (hit_record.normal, cos)
+    } else {
+        (-hit_record.normal, -cos)
+    }
+}
+
+/// Fraction of light left after the ray has travelled through an absorbing
+/// medium to reach the hit, if it started inside the object
+fn transmittance(ray: &Ray, hit_record: &HitRecord, absorption: Vec3) -> Vec3 {
+    if hit_record.front_face {
+        // Arrived from outside
+        return Vec3(1.0, 1.0, 1.0);
+    }
+    let distance = hit_record.t * ray.dir.magnitude();
+    (-distance * absorption).map(f32::exp)
+}
+
+fn same_side(normal: Vec3, a: Vec3, b: Vec3) -> bool {
+    normal.dot(a) * normal.dot(b) > 0.0
 }
 
 fn reflect(incident: Vec3, normal: Vec3) -> Vec3 {
     incident - 2.0 * incident.dot(normal) * normal
 }
 
-fn refract(incident: Vec3, normal: Vec3, ratio: f32) -> Option<Vec3> {
+pub(crate) fn refract(incident: Vec3, normal: Vec3, ratio: f32) -> Option<Vec3> {
     let cos_i = -incident.dot(normal);
     let cos_r_sq = 1.0 - (ratio * ratio) * (1.0 - (cos_i * cos_i));
 
@@ -97,3 +717,183 @@ fn schlick(cos_i: f32, ref_idx: f32) -> f32 {
     let r0 = r0_sqrt * r0_sqrt;
     r0 + (1.0 - r0) * (1.0 - cos_i).powf(5.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::hittable::{HittableList, Quad};
+    use crate::{RenderSettings, Scene};
+
+    /// Average attenuation of light scattered by `material` that arrives at an
+    /// angle with cosine `cos_i` to the normal, which is the fraction of the
+    /// light it reflects or transmits
+    fn albedo(material: &Material, cos_i: f32) -> Vec3 {
+        let hit_record = HitRecord {
+            intersection: Vec3::default(),
+            normal: Vec3(0.0, 1.0, 0.0),
+            t: 1.0,
+            uv: (0.0, 0.0),
+            material,
+            object_id: 0,
+            material_id: 0,
+            front_face: true,
+        };
+        let ray = Ray {
+            origin: Vec3((1.0 - cos_i * cos_i).sqrt(), cos_i, 0.0),
+            dir: Vec3(-(1.0 - cos_i * cos_i).sqrt(), -cos_i, 0.0),
+        };
+        let mut rng = rand::thread_rng();
+        let samples = 20_000;
+        let total = (0..samples)
+            .filter_map(|_| material.scatter(&ray, &hit_record, &mut rng))
+            .map(|(_, attenuation)| attenuation)
+            .sum::<Vec3>();
+        total / samples as f32
+    }
+
+    /// Whether every component of `v` is within `range`
+    fn within(v: Vec3, range: std::ops::RangeInclusive<f32>) -> bool {
+        [v.0, v.1, v.2].iter().all(|c| range.contains(c))
+    }
+
+    /// Abbe number, from the indices at the F, d and C Fraunhofer lines
+    fn abbe_number(dispersion: &Dispersion) -> f32 {
+        (dispersion.ior(587.6) - 1.0) / (dispersion.ior(486.1) - dispersion.ior(656.3))
+    }
+
+    #[test]
+    fn presets_match_published_indices() {
+        for (name, dispersion, n_d, abbe) in [
+            ("BK7", Dispersion::BK7, 1.5168, 64.17),
+            ("FLINT", Dispersion::FLINT, 1.7847, 25.68),
+            ("DIAMOND", Dispersion::DIAMOND, 2.4175, 55.3),
+        ] {
+            let ior = dispersion.ior(Dispersion::YELLOW);
+            assert!((ior - n_d).abs() < 1e-3, "{name}: {ior}");
+            let v = abbe_number(&dispersion);
+            assert!((v - abbe).abs() < 0.5, "{name}: {v}");
+        }
+    }
+
+    #[test]
+    fn blue_light_bends_more_than_red() {
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+        assert!((cauchy.ior(500.0) - 1.516).abs() < 1e-6);
+        for dispersion in [
+            cauchy,
+            Dispersion::BK7,
+            Dispersion::FLINT,
+            Dispersion::DIAMOND,
+        ] {
+            assert!(dispersion.ior(400.0) > dispersion.ior(550.0));
+            assert!(dispersion.ior(550.0) > dispersion.ior(700.0));
+        }
+    }
+
+    #[test]
+    fn tinted_glass_needs_a_positive_distance() {
+        assert!(Material::tinted_glass(1.5, Vec3(0.5, 0.5, 0.5), 0.0).is_none());
+        assert!(Material::tinted_glass(1.5, Vec3(0.5, 0.5, 0.5), -1.0).is_none());
+        assert!(Material::tinted_glass(1.5, Vec3(0.5, 0.5, 0.5), 1.0).is_some());
+    }
+
+    #[test]
+    fn box_of_tinted_glass_quads_absorbs_light_behind_it() {
+        // Halves red light every unit, and lets green and blue through. An
+        // index of 1 keeps every ray going straight through.
+        let glass = Material::tinted_glass(1.0, Vec3(0.5, 1.0, 1.0), 1.0).unwrap();
+        let mut objects = HittableList::new();
+        // A 2x2x2 cube with its faces' normals pointing out
+        let (min, max) = (Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0));
+        let (dx, dy, dz) = (
+            Vec3(2.0, 0.0, 0.0),
+            Vec3(0.0, 2.0, 0.0),
+            Vec3(0.0, 0.0, 2.0),
+        );
+        for (q, u, v) in [
+            (Vec3(min.0, min.1, max.2), dx, dy),
+            (Vec3(max.0, min.1, max.2), -dz, dy),
+            (Vec3(max.0, min.1, min.2), -dx, dy),
+            (Vec3(min.0, min.1, min.2), dz, dy),
+            (Vec3(min.0, max.1, max.2), dx, -dz),
+            (Vec3(min.0, min.1, min.2), dx, dz),
+        ] {
+            objects.push_quad(Quad::new(q, u, v, glass.clone()));
+        }
+        objects.push_quad(Quad::new(
+            Vec3(-5.0, -5.0, -3.0),
+            Vec3(10.0, 0.0, 0.0),
+            Vec3(0.0, 10.0, 0.0),
+            Material::Light(Vec3(1.0, 1.0, 1.0)),
+        ));
+        let scene = Scene {
+            objects,
+            camera: Camera::orthographic(
+                Vec3(0.0, 0.0, 5.0),
+                Vec3(0.0, 0.0, 0.0),
+                Vec3(0.0, 1.0, 0.0),
+                1.0,
+                1.0,
+            ),
+        };
+        let settings = RenderSettings {
+            num_samples: 4,
+            depth: 4,
+            ..Default::default()
+        };
+
+        // Two units of glass between the camera and the light
+        for pixel in scene.render(4, 4, &settings).beauty {
+            assert!((pixel - Vec3(0.25, 1.0, 1.0)).magnitude() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn layered_material_never_reflects_more_than_arrives() {
+        let white = Material::Lambertian(Texture::Constant(Vec3(1.0, 1.0, 1.0)));
+        let layered = |absorption| Material::Layered {
+            ior: 1.5,
+            roughness: 0.1,
+            absorption,
+            thickness: 1.0,
+            base: Box::new(white.clone()),
+        };
+        for cos_i in [1.0, 0.5, 0.1] {
+            // Only the light reflected back down by the top of the coat is lost
+            let clear = albedo(&layered(Vec3::default()), cos_i);
+            assert!(within(clear, 0.8..=1.0));
+            let tinted = albedo(&layered(Vec3(0.0, 0.5, 2.0)), cos_i);
+            assert!(within(tinted, 0.0..=1.0));
+            assert!(tinted.0 > tinted.1 && tinted.1 > tinted.2);
+        }
+    }
+
+    #[test]
+    fn mix_weights_its_components_by_the_mask() {
+        let black = Material::Lambertian(Texture::Constant(Vec3::default()));
+        let white = Material::Lambertian(Texture::Constant(Vec3(1.0, 1.0, 1.0)));
+        let mix = Material::mix(black, white.clone(), 0.25);
+        assert!(within(albedo(&mix, 0.7), 0.22..=0.28));
+
+        let mirror = Material::Metal(Vec3(1.0, 1.0, 1.0), 0.0);
+        let mix = Material::mix(white, mirror, 0.5);
+        assert!(within(albedo(&mix, 0.7), 0.99..=1.0));
+        assert!(mix.is_specular());
+    }
+
+    #[test]
+    fn glowing_surface_reflects_only_its_scattering_part() {
+        let grey = Material::Lambertian(Texture::Constant(Vec3(0.5, 0.5, 0.5)));
+        let glow = Material::combine(Material::Light(Vec3(2.0, 1.0, 0.5)), grey.clone());
+        assert!(glow.is_emissive() && !grey.is_emissive());
+        assert!(glow.emit(Vec3::default()) == Vec3(2.0, 1.0, 0.5));
+        assert!(within(albedo(&glow, 0.7), 0.49..=0.51));
+
+        let light = Material::combine(
+            Material::Light(Vec3(1.0, 1.0, 1.0)),
+            Material::Light(Vec3(1.0, 1.0, 1.0)),
+        );
+        assert!(!light.scatters() && light.emit(Vec3::default()) == Vec3(2.0, 2.0, 2.0));
+    }
+}
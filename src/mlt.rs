@@ -0,0 +1,337 @@
+//! Primary sample space Metropolis light transport (Kelemen et al. 2002). The
+//! path tracer is treated as a function of the uniform random numbers it
+//! consumes, and Markov chains explore that space by mutating those numbers,
+//! spending more samples on the paths that carry the most light.
+
+use indicatif::ProgressBar;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rayon::prelude::*;
+
+use crate::integrator::{render_hit_aovs, Integrator, PathTracer, RayIntegrator};
+use crate::output::RenderOutput;
+use crate::vec3::Vec3;
+use crate::{RenderSettings, Scene};
+
+/// Number of independent paths used to estimate the overall brightness of the
+/// image, and to pick where each chain starts
+const NUM_BOOTSTRAP: u64 = 100_000;
+const NUM_CHAINS: u64 = 1_000;
+
+/// Largest `f32` below 1
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Primary sample space Metropolis light transport, which mutates the random
+/// numbers driving the path tracer to find paths that are hard to sample, such
+/// as light through small openings. Makes `num_samples` mutations per pixel,
+/// and doesn't support reconstruction filters or the auxiliary passes that
+/// measure light.
+pub struct Metropolis {
+    /// Chance of each mutation picking a completely new path
+    pub large_step_probability: f32,
+    /// Standard deviation of the perturbation made by small mutations
+    pub mutation_size: f32,
+}
+
+impl Integrator for Metropolis {
+    fn render(
+        &self,
+        scene: &Scene,
+        height: u32,
+        width: u32,
+        settings: &RenderSettings,
+    ) -> RenderOutput {
+        render(
+            scene,
+            height,
+            width,
+            settings,
+            self.large_step_probability,
+            self.mutation_size,
+        )
+    }
+}
+
+fn render(
+    scene: &Scene,
+    height: u32,
+    width: u32,
+    settings: &RenderSettings,
+    large_step_probability: f32,
+    mutation_size: f32,
+) -> RenderOutput {
+    let num_pixels = (width * height) as usize;
+    let total_mutations = settings.num_samples as u64 * num_pixels as u64;
+    let new_sampler = |seed| MltSampler::new(seed, mutation_size, large_step_probability);
+
+    // The average brightness of a path gives the normalisation of the image,
+    // since the chains only determine how light is distributed across it
+    let num_bootstrap = NUM_BOOTSTRAP.min(total_mutations).max(1);
+    let weights: Vec<f32> = (0..num_bootstrap)
+        .into_par_iter()
+        .map(|seed| {
+            sample_path(scene, settings, &mut new_sampler(seed))
+                .2
+                .luminance()
+        })
+        .collect();
+    let cdf: Vec<f32> = weights
+        .iter()
+        .scan(0.0, |total, &w| {
+            *total += w;
+            Some(*total)
+        })
+        .collect();
+    let total_weight = cdf[cdf.len() - 1];
+    let brightness = total_weight / num_bootstrap as f32;
+
+    let num_chains = NUM_CHAINS.min(total_mutations);
+    if brightness <= 0.0 || num_chains == 0 {
+        return RenderOutput {
+            width,
+            height,
+            beauty: vec![Vec3::default(); num_pixels],
+            aovs: render_hit_aovs(scene, height, width, settings),
+        };
+    }
+
+    let bar = ProgressBar::new(num_chains);
+    let num_groups = (rayon::current_num_threads() as u64).min(num_chains);
+
+    let image = (0..num_groups)
+        .into_par_iter()
+        .map(|group| {
+            let mut image = vec![Vec3::default(); num_pixels];
+            let mut rng = rand::thread_rng();
+
+            for chain in (group..num_chains).step_by(num_groups as usize) {
+                let mutations =
+                    total_mutations / num_chains + u64::from(chain < total_mutations % num_chains);
+
+                // Starting from a path picked in proportion to its brightness
+                // means the chain doesn't need a burn-in period
+                let u = rng.gen::<f32>() * total_weight;
+                let seed = cdf.partition_point(|&c| c <= u).min(cdf.len() - 1);
+                let mut sampler = new_sampler(seed as u64);
+                let mut current = sample_path(scene, settings, &mut sampler);
+
+                for _ in 0..mutations {
+                    sampler.start_iteration();
+                    let proposed = sample_path(scene, settings, &mut sampler);
+
+                    let current_weight = current.2.luminance();
+                    let proposed_weight = proposed.2.luminance();
+                    let accept = if current_weight > 0.0 {
+                        (proposed_weight / current_weight).min(1.0)
+                    } else {
+                        1.0
+                    };
+
+                    // Both paths contribute in expectation, which reduces noise
+                    if accept > 0.0 {
+                        let value = (accept / proposed_weight) * proposed.2;
+                        splat(&mut image, width, height, proposed.0, proposed.1, value);
+                    }
+                    if current_weight > 0.0 {
+                        let value = ((1.0 - accept) / current_weight) * current.2;
+                        splat(&mut image, width, height, current.0, current.1, value);
+                    }
+
+                    if rng.gen::<f32>() < accept {
+                        current = proposed;
+                        sampler.accept();
+                    } else {
+                        sampler.reject();
+                    }
+                }
+
+                bar.inc(1);
+            }
+
+            image
+        })
+        .reduce(
+            || vec![Vec3::default(); num_pixels],
+            |a, b| a.into_iter().zip(b).map(|(a, b)| a + b).collect(),
+        );
+
+    bar.finish();
+
+    let scale = brightness / settings.num_samples as f32;
+    RenderOutput {
+        width,
+        height,
+        beauty: image.into_iter().map(|col| scale * col).collect(),
+        aovs: render_hit_aovs(scene, height, width, settings),
+    }
+}
+
+/// Traces a camera path using the sampler's random numbers, returning where
+/// it lands on the image and the light it carries
+fn sample_path(
+    scene: &Scene,
+    settings: &RenderSettings,
+    sampler: &mut MltSampler,
+) -> (f32, f32, Vec3) {
+    let x = sampler.gen::<f32>();
+    let y = sampler.gen::<f32>();
+    let Some(ray) = scene.camera.get_ray(x, y, sampler) else {
+        return (x, y, Vec3::default());
+    };
+    let radiance = PathTracer.radiance(&ray, scene, settings, sampler).total();
+
+    if radiance.luminance().is_finite() {
+        (x, y, radiance)
+    } else {
+        (x, y, Vec3::default())
+    }
+}
+
+fn splat(image: &mut [Vec3], width: u32, height: u32, x: f32, y: f32, value: Vec3) {
+    let column = ((x * width as f32) as u32).min(width - 1);
+    let row = (((1.0 - y) * height as f32) as u32).min(height - 1);
+    let n = (row * width + column) as usize;
+    image[n] = image[n] + value;
+}
+
+struct PrimarySample {
+    value: f32,
+    /// Iteration in which `value` was last changed
+    last_modified: u64,
+    backup_value: f32,
+    backup_modified: u64,
+}
+
+impl PrimarySample {
+    fn backup(&mut self) {
+        self.backup_value = self.value;
+        self.backup_modified = self.last_modified;
+    }
+
+    fn restore(&mut self) {
+        self.value = self.backup_value;
+        self.last_modified = self.backup_modified;
+    }
+}
+
+/// Provides the random numbers consumed by the path tracer, and mutates them
+/// between iterations. Each mutation is either a large step, which picks
+/// completely new values, or a small step, which perturbs the existing ones.
+/// Values are only generated when they are used, so paths can consume any
+/// number of them.
+struct MltSampler {
+    rng: StdRng,
+    samples: Vec<PrimarySample>,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    /// Index of the next value to hand out in the current iteration
+    index: usize,
+    mutation_size: f32,
+    large_step_probability: f32,
+}
+
+impl MltSampler {
+    fn new(seed: u64, mutation_size: f32, large_step_probability: f32) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            samples: Vec::new(),
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0,
+            mutation_size,
+            large_step_probability,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f32>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.last_modified == self.iteration {
+                sample.restore();
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let index = self.index;
+        self.index += 1;
+        if index >= self.samples.len() {
+            // A value that has never been used is as good as one picked at the
+            // last large step
+            let value = self.rng.gen();
+            self.samples.push(PrimarySample {
+                value,
+                last_modified: self.last_large_step,
+                backup_value: value,
+                backup_modified: self.last_large_step,
+            });
+        }
+
+        let sample = &mut self.samples[index];
+
+        // Values that weren't used since the last large step are out of date
+        if sample.last_modified < self.last_large_step {
+            sample.value = self.rng.gen();
+            sample.last_modified = self.last_large_step;
+        }
+
+        sample.backup();
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else {
+            // Catch up on the small steps missed since the value was last used,
+            // which add up to a single wider step
+            let missed_steps = (self.iteration - sample.last_modified) as f32;
+            let sigma = self.mutation_size * missed_steps.sqrt();
+            sample.value += sigma * standard_normal(&mut self.rng);
+            sample.value = (sample.value - sample.value.floor()).min(ONE_MINUS_EPSILON);
+        }
+        sample.last_modified = self.iteration;
+
+        sample.value
+    }
+}
+
+impl RngCore for MltSampler {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_sample() as f64 * (u32::MAX as f64 + 1.0)) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_sample() as f64 * (u64::MAX as f64 + 1.0)) as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Samples a normal distribution with a mean of zero and a standard deviation
+/// of one, using the Box-Muller transform
+fn standard_normal(rng: &mut StdRng) -> f32 {
+    let u1 = 1.0 - rng.gen::<f32>();
+    let u2 = rng.gen::<f32>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
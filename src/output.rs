@@ -0,0 +1,106 @@
+use exr::prelude::*;
+
+use crate::aov::Aov;
+use crate::denoise::{denoise, DenoiseGuides, DenoiseSettings};
+use crate::vec3::Vec3;
+
+/// The result of rendering a scene: the beauty image, plus any auxiliary
+/// passes that were requested. Pixels are stored row-major, starting from the
+/// top-left corner.
+pub struct RenderOutput {
+    pub width: u32,
+    pub height: u32,
+    pub beauty: Vec<Vec3>,
+    pub aovs: Vec<(Aov, Vec<Vec3>)>,
+}
+
+impl RenderOutput {
+    pub fn aov(&self, aov: Aov) -> Option<&[Vec3]> {
+        self.aovs
+            .iter()
+            .find(|(a, _)| *a == aov)
+            .map(|(_, pixels)| pixels.as_slice())
+    }
+
+    /// Scales the beauty image and the passes that measure light
+    pub(crate) fn apply_exposure(&mut self, exposure: f32) {
+        for col in &mut self.beauty {
+            *col = exposure * *col;
+        }
+        for (aov, pixels) in &mut self.aovs {
+            let scale = match aov {
+                Aov::Direct | Aov::Indirect | Aov::Emission => exposure,
+                Aov::Variance => exposure * exposure,
+                _ => continue,
+            };
+            for col in pixels {
+                *col = scale * *col;
+            }
+        }
+    }
+
+    /// Denoises the beauty image, using the albedo, normal and depth passes as
+    /// guides. Returns `None` if any of those passes were not rendered.
+    pub fn denoised(&self, settings: &DenoiseSettings) -> Option<Vec<Vec3>> {
+        let guides = DenoiseGuides {
+            albedo: self.aov(Aov::Albedo)?,
+            normal: self.aov(Aov::Normal)?,
+            depth: self.aov(Aov::Depth)?,
+        };
+
+        Some(denoise(
+            &self.beauty,
+            self.width,
+            self.height,
+            &guides,
+            settings,
+        ))
+    }
+
+    /// Writes the beauty image and every auxiliary pass as layers of a single
+    /// multi-layer EXR file
+    pub fn write_exr(&self, path: &str) -> Result<()> {
+        let mut layers = vec![self.layer("beauty", &["R", "G", "B"], &self.beauty)];
+        for (aov, pixels) in &self.aovs {
+            layers.push(self.layer(aov.name(), aov.channels(), pixels));
+        }
+
+        let size = (self.width as usize, self.height as usize);
+        let attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+        Image::from_layers(attributes, layers).write().to_file(path)
+    }
+
+    /// Writes a single auxiliary pass to its own EXR file
+    pub fn write_aov_exr(&self, aov: Aov, path: &str) -> Result<()> {
+        let pixels = self.aov(aov).ok_or_else(|| {
+            Error::Invalid(format!("the {} pass was not rendered", aov.name()).into())
+        })?;
+
+        Image::from_layer(self.layer(aov.name(), aov.channels(), pixels))
+            .write()
+            .to_file(path)
+    }
+
+    fn layer(
+        &self,
+        name: &str,
+        channel_names: &[&str],
+        pixels: &[Vec3],
+    ) -> Layer<AnyChannels<FlatSamples>> {
+        let channels = channel_names
+            .iter()
+            .enumerate()
+            .map(|(i, &channel_name)| {
+                let samples = pixels.iter().map(|p| [p.0, p.1, p.2][i]).collect();
+                AnyChannel::new(channel_name, FlatSamples::F32(samples))
+            })
+            .collect();
+
+        Layer::new(
+            (self.width as usize, self.height as usize),
+            LayerAttributes::named(name),
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(channels),
+        )
+    }
+}
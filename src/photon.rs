@@ -0,0 +1,499 @@
+//! Stochastic progressive photon mapping. Each iteration traces one camera
+//! path per pixel through specular surfaces to a "visible point" on a diffuse
+//! surface, then traces photons from the lights and gathers the ones that land
+//! near each visible point. The gather radius of each pixel shrinks as photons
+//! accumulate, so the estimate converges to the correct result, including
+//! caustics that path tracing struggles with.
+
+use indicatif::ProgressBar;
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::integrator::{render_hit_aovs, Integrator};
+use crate::material::Material;
+use crate::output::RenderOutput;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use crate::{RenderSettings, Scene};
+
+/// Fraction of the photons found in each iteration that are kept, which
+/// controls how quickly the gather radius shrinks
+const ALPHA: f32 = 2.0 / 3.0;
+
+/// Used to pick an initial gather radius when none is given, relative to the
+/// typical distance from the camera to what it sees
+const RADIUS_FRACTION: f32 = 0.005;
+
+struct Photon {
+    point: Vec3,
+    /// Unit vector pointing back along the direction the photon arrived from
+    wi: Vec3,
+    /// Flux carried by the photon
+    beta: Vec3,
+}
+
+/// Where a camera path first reached a diffuse surface
+struct VisiblePoint<'a> {
+    point: Vec3,
+    normal: Vec3,
+    wo: Vec3,
+    material: &'a Material,
+    /// Throughput of the camera path up to this point
+    beta: Vec3,
+    /// Distance travelled from the camera
+    distance: f32,
+}
+
+struct PixelState<'a> {
+    /// Sum of the light found directly by each camera path
+    direct: Vec3,
+    visible: Option<VisiblePoint<'a>>,
+    radius: f32,
+    /// Number of photons gathered so far, after being scaled down by `ALPHA`
+    photon_count: f32,
+    /// Flux gathered from photons so far, scaled to the current radius
+    flux: Vec3,
+}
+
+/// Stochastic progressive photon mapping, which resolves caustics. Renders
+/// `num_samples` iterations, and doesn't support reconstruction filters or
+/// the auxiliary passes that measure light.
+pub struct ProgressivePhotonMapping {
+    pub photons_per_iteration: u32,
+    /// Initial radius around each pixel's visible point to gather photons
+    /// from. Picked based on the scene's scale if not given.
+    pub initial_radius: Option<f32>,
+}
+
+impl Integrator for ProgressivePhotonMapping {
+    fn render(
+        &self,
+        scene: &Scene,
+        height: u32,
+        width: u32,
+        settings: &RenderSettings,
+    ) -> RenderOutput {
+        render(
+            scene,
+            height,
+            width,
+            settings,
+            self.photons_per_iteration,
+            self.initial_radius,
+        )
+    }
+}
+
+fn render(
+    scene: &Scene,
+    height: u32,
+    width: u32,
+    settings: &RenderSettings,
+    photons_per_iteration: u32,
+    initial_radius: Option<f32>,
+) -> RenderOutput {
+    let bar = ProgressBar::new(settings.num_samples as u64);
+    let mut pixels: Vec<PixelState> = (0..width * height)
+        .map(|_| PixelState {
+            direct: Vec3::default(),
+            visible: None,
+            radius: initial_radius.unwrap_or(0.0),
+            photon_count: 0.0,
+            flux: Vec3::default(),
+        })
+        .collect();
+
+    for iteration in 0..settings.num_samples {
+        pixels.par_iter_mut().enumerate().for_each(|(n, pixel)| {
+            let mut rng = rand::thread_rng();
+            let column = n as u32 % width;
+            let row = n as u32 / width;
+            let x = (column as f32 + rng.gen::<f32>()) / width as f32;
+            let y = 1.0 - (row as f32 + rng.gen::<f32>()) / height as f32;
+
+            match scene.camera.get_ray(x, y, &mut rng) {
+                Some(ray) => trace_camera_path(&ray, &scene.objects, settings, pixel, &mut rng),
+                None => pixel.visible = None,
+            }
+        });
+
+        if iteration == 0 && initial_radius.is_none() {
+            let radius = estimate_radius(&pixels);
+            pixels.iter_mut().for_each(|pixel| pixel.radius = radius);
+        }
+
+        let photons: Vec<Photon> = (0..photons_per_iteration)
+            .into_par_iter()
+            .flat_map_iter(|_| {
+                trace_photon(&scene.objects, settings.depth, &mut rand::thread_rng())
+            })
+            .collect();
+        let photon_map = PhotonMap::new(photons);
+
+        pixels
+            .par_iter_mut()
+            .for_each(|pixel| gather_photons(&photon_map, pixel));
+
+        bar.inc(1);
+    }
+
+    bar.finish();
+
+    let iterations = settings.num_samples.max(1) as f32;
+    let total_photons = iterations * photons_per_iteration as f32;
+    let beauty = pixels
+        .iter()
+        .map(|pixel| {
+            let area = std::f32::consts::PI * pixel.radius * pixel.radius;
+            let indirect = if area > 0.0 && total_photons > 0.0 {
+                pixel.flux / (total_photons * area)
+            } else {
+                Vec3::default()
+            };
+            pixel.direct / iterations + indirect
+        })
+        .collect();
+
+    RenderOutput {
+        width,
+        height,
+        beauty,
+        aovs: render_hit_aovs(scene, height, width, settings),
+    }
+}
+
+/// Follows a camera ray through specular bounces until it reaches a diffuse
+/// surface, where direct lighting is estimated and the visible point recorded
+fn trace_camera_path<'a, R: Rng + ?Sized>(
+    ray: &Ray,
+    objects: &'a HittableList,
+    settings: &RenderSettings,
+    pixel: &mut PixelState<'a>,
+    rng: &mut R,
+) {
+    pixel.visible = None;
+    let mut ray = ray.clone();
+    let mut beta = Vec3(1.0, 1.0, 1.0);
+    let mut distance = 0.0;
+
+    for _ in 0..settings.depth {
+        let Some(hit_record) = objects.hit(&ray, 0.001..f32::MAX) else {
+            break;
+        };
+        distance += hit_record.t * ray.dir.magnitude();
+        pixel.direct = pixel.direct + beta * hit_record.material.emit(hit_record.intersection);
+
+        if !hit_record.material.is_specular() {
+            // Lights that only emit have nothing to reflect
+            if !hit_record.material.scatters() {
+                break;
+            }
+
+            let wo = -ray.dir.normalize();
+            pixel.direct = pixel.direct + beta * direct_light(objects, &hit_record, wo, rng);
+            pixel.visible = Some(VisiblePoint {
+                point: hit_record.intersection,
+                normal: hit_record.normal,
+                wo,
+                material: hit_record.material,
+                beta,
+                distance,
+            });
+            break;
+        }
+
+        let Some((new_ray, attenuation)) = hit_record.material.scatter(&ray, &hit_record, rng)
+        else {
+            break;
+        };
+        beta = beta * attenuation;
+        ray = new_ray;
+    }
+}
+
+/// Estimates the light arriving at a diffuse surface straight from a light,
+/// which photons don't account for
+fn direct_light<R: Rng + ?Sized>(
+    objects: &HittableList,
+    hit_record: &HitRecord,
+    wo: Vec3,
+    rng: &mut R,
+) -> Vec3 {
+    let Some(light) = objects.sample_light(rng) else {
+        return Vec3::default();
+    };
+
+    let d = light.point - hit_record.intersection;
+    let dist = d.magnitude();
+    let wi = d / dist;
+    if !light.both_sides && light.normal.dot(wi) > 0.0 {
+        return Vec3::default();
+    }
+
+    let shadow_ray = Ray {
+        origin: hit_record.intersection,
+        dir: wi,
+    };
+    if objects.hit(&shadow_ray, 0.001..(dist - 0.001)).is_some() {
+        return Vec3::default();
+    }
+
+    let f = hit_record
+        .material
+        .eval(hit_record.intersection, hit_record.normal, wo, wi);
+    let geometry = hit_record.normal.dot(wi).abs() * light.normal.dot(wi).abs() / (dist * dist);
+    (geometry / light.pdf) * f * light.emission
+}
+
+/// Traces a single photon from a light, returning every diffuse surface it
+/// lands on after its first bounce
+fn trace_photon<R: Rng + ?Sized>(objects: &HittableList, depth: u32, rng: &mut R) -> Vec<Photon> {
+    let mut photons = Vec::new();
+    let Some(light) = objects.sample_light(rng) else {
+        return photons;
+    };
+    let (dir, pdf_dir) = light.sample_direction(rng);
+    if pdf_dir <= 0.0 {
+        return photons;
+    }
+
+    let mut beta = (light.normal.dot(dir).abs() / (light.pdf * pdf_dir)) * light.emission;
+    let mut ray = Ray {
+        origin: light.point,
+        dir,
+    };
+
+    for bounce in 0..depth {
+        let Some(hit_record) = objects.hit(&ray, 0.001..f32::MAX) else {
+            break;
+        };
+
+        // Light arriving straight from a light is handled by `direct_light`
+        if bounce > 0 && !hit_record.material.is_specular() {
+            photons.push(Photon {
+                point: hit_record.intersection,
+                wi: -ray.dir.normalize(),
+                beta,
+            });
+        }
+
+        let Some((new_ray, attenuation)) = hit_record.material.scatter(&ray, &hit_record, rng)
+        else {
+            break;
+        };
+        beta = beta * attenuation;
+        ray = new_ray;
+    }
+
+    photons
+}
+
+/// Adds the photons near a pixel's visible point to its estimate, and shrinks
+/// its gather radius
+fn gather_photons(photon_map: &PhotonMap, pixel: &mut PixelState) {
+    let Some(visible) = &pixel.visible else {
+        return;
+    };
+
+    let mut flux = Vec3::default();
+    let mut count = 0;
+    photon_map.for_each_within(visible.point, pixel.radius, |photon| {
+        let f = visible
+            .material
+            .eval(visible.point, visible.normal, visible.wo, photon.wi);
+        flux = flux + f * photon.beta;
+        count += 1;
+    });
+
+    if count == 0 {
+        return;
+    }
+
+    let new_count = pixel.photon_count + ALPHA * count as f32;
+    let new_radius = pixel.radius * (new_count / (pixel.photon_count + count as f32)).sqrt();
+    let shrink = (new_radius / pixel.radius).powi(2);
+
+    pixel.flux = shrink * (pixel.flux + visible.beta * flux);
+    pixel.photon_count = new_count;
+    pixel.radius = new_radius;
+}
+
+fn estimate_radius(pixels: &[PixelState]) -> f32 {
+    let mut distances: Vec<f32> = pixels
+        .iter()
+        .filter_map(|pixel| pixel.visible.as_ref().map(|v| v.distance))
+        .collect();
+    if distances.is_empty() {
+        return 1.0;
+    }
+
+    let mid = distances.len() / 2;
+    let (_, median, _) = distances.select_nth_unstable_by(mid, f32::total_cmp);
+    RADIUS_FRACTION * *median
+}
+
+/// A kd-tree of photons, stored implicitly: the photon at the middle of any
+/// range splits the rest of that range along its axis
+struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    fn for_each_within(&self, point: Vec3, radius: f32, mut f: impl FnMut(&Photon)) {
+        search(&self.photons, &self.axes, point, radius * radius, &mut f);
+    }
+}
+
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.is_empty() {
+        return;
+    }
+
+    // Split along the axis where the photons are most spread out
+    let (min, max) = photons.iter().fold(
+        (
+            Vec3(f32::MAX, f32::MAX, f32::MAX),
+            Vec3(f32::MIN, f32::MIN, f32::MIN),
+        ),
+        |(min, max), p| {
+            (
+                Vec3(
+                    min.0.min(p.point.0),
+                    min.1.min(p.point.1),
+                    min.2.min(p.point.2),
+                ),
+                Vec3(
+                    max.0.max(p.point.0),
+                    max.1.max(p.point.1),
+                    max.2.max(p.point.2),
+                ),
+            )
+        },
+    );
+    let extent = max - min;
+    let axis = if extent.0 > extent.1 && extent.0 > extent.2 {
+        0
+    } else if extent.1 > extent.2 {
+        1
+    } else {
+        2
+    };
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| {
+        component(a.point, axis).total_cmp(&component(b.point, axis))
+    });
+    axes[mid] = axis;
+
+    let (left, rest) = photons.split_at_mut(mid);
+    let (left_axes, rest_axes) = axes.split_at_mut(mid);
+    if left.len() > 10_000 {
+        rayon::join(
+            || build(left, left_axes),
+            || build(&mut rest[1..], &mut rest_axes[1..]),
+        );
+    } else {
+        build(left, left_axes);
+        build(&mut rest[1..], &mut rest_axes[1..]);
+    }
+}
+
+fn search(
+    photons: &[Photon],
+    axes: &[usize],
+    point: Vec3,
+    radius_sq: f32,
+    f: &mut impl FnMut(&Photon),
+) {
+    if photons.is_empty() {
+        return;
+    }
+
+    let mid = photons.len() / 2;
+    let photon = &photons[mid];
+    let d = photon.point - point;
+    if d.dot(d) <= radius_sq {
+        f(photon);
+    }
+
+    let axis = axes[mid];
+    let offset = component(point, axis) - component(photon.point, axis);
+    let (near, far) = if offset < 0.0 {
+        (
+            (&photons[..mid], &axes[..mid]),
+            (&photons[mid + 1..], &axes[mid + 1..]),
+        )
+    } else {
+        (
+            (&photons[mid + 1..], &axes[mid + 1..]),
+            (&photons[..mid], &axes[..mid]),
+        )
+    };
+
+    search(near.0, near.1, point, radius_sq, f);
+    if offset * offset <= radius_sq {
+        search(far.0, far.1, point, radius_sq, f);
+    }
+}
+
+fn component(v: Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => v.0,
+        1 => v.1,
+        _ => v.2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn range_query_finds_exactly_the_photons_within_the_radius() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut random_point = || Vec3(rng.gen(), rng.gen(), 0.2 * rng.gen::<f32>());
+        // Photons are told apart by the flux they carry
+        let mut points: Vec<Vec3> = (0..2000).map(|_| random_point()).collect();
+        // Repeated points land on splitting planes
+        points.extend_from_within(..100);
+        let queries: Vec<Vec3> = (0..50).map(|_| random_point()).collect();
+
+        let photons = points
+            .iter()
+            .enumerate()
+            .map(|(i, &point)| Photon {
+                point,
+                wi: Vec3(0.0, 0.0, 1.0),
+                beta: Vec3(i as f32, 0.0, 0.0),
+            })
+            .collect();
+        let map = PhotonMap::new(photons);
+
+        for point in queries {
+            for radius in [0.0, 0.05, 0.2] {
+                let mut found = Vec::new();
+                map.for_each_within(point, radius, |photon| found.push(photon.beta.0 as usize));
+                found.sort_unstable();
+
+                let expected: Vec<usize> = (0..points.len())
+                    .filter(|&i| {
+                        let d = points[i] - point;
+                        d.dot(d) <= radius * radius
+                    })
+                    .collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+}
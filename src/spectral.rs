@@ -0,0 +1,318 @@
+//! Spectral rendering. Each camera sample carries a handful of wavelengths
+//! through the scene instead of red, green and blue. Colours in the scene are
+//! upsampled to spectra with Smits' method, and the light arriving at the
+//! camera is converted back to sRGB through the CIE colour matching functions.
+
+use std::ops::{Add, Div, Mul};
+use std::sync::OnceLock;
+
+use rand::Rng;
+
+use crate::aov::FirstHit;
+use crate::hittable::Hittable;
+use crate::integrator::{Radiance, RayIntegrator};
+use crate::ray::Ray;
+use crate::subsurface::{medium_after, Medium};
+use crate::vec3::Vec3;
+use crate::{RenderSettings, Scene, BACKGROUND_COLOR};
+
+/// Number of wavelengths traced together along each path
+pub(crate) const NUM_WAVELENGTHS: usize = 4;
+
+/// Range of visible wavelengths, in nanometres
+const LAMBDA_MIN: f32 = 380.0;
+const LAMBDA_MAX: f32 = 720.0;
+
+/// Wavelengths carried along a path. The first, hero wavelength is picked at
+/// random and the rest are spaced evenly after it, wrapping around the visible
+/// range.
+#[derive(Copy, Clone)]
+pub(crate) struct SampledWavelengths {
+    pub lambda: [f32; NUM_WAVELENGTHS],
+    /// Probability density of each wavelength, zero once it has been dropped
+    pub pdf: [f32; NUM_WAVELENGTHS],
+}
+
+impl SampledWavelengths {
+    pub fn sample<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let u = rng.gen::<f32>();
+        let lambda = std::array::from_fn(|i| {
+            let offset = (u + i as f32 / NUM_WAVELENGTHS as f32).fract();
+            LAMBDA_MIN + offset * (LAMBDA_MAX - LAMBDA_MIN)
+        });
+        Self {
+            lambda,
+            pdf: [1.0 / (LAMBDA_MAX - LAMBDA_MIN); NUM_WAVELENGTHS],
+        }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// Drops every wavelength but the hero, for when they would need to take
+    /// different paths. The hero's density is scaled so the estimate stays
+    /// unbiased.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1..].iter().all(|&pdf| pdf == 0.0) {
+            return;
+        }
+        self.pdf[0] /= NUM_WAVELENGTHS as f32;
+        self.pdf[1..].fill(0.0);
+    }
+
+    /// Converts light at each wavelength to linear sRGB, white balanced so that
+    /// a flat spectrum of 1 becomes white
+    pub fn to_rgb(self, spectrum: SampledSpectrum) -> Vec3 {
+        let mut xyz = Vec3::default();
+        for i in 0..NUM_WAVELENGTHS {
+            if self.pdf[i] > 0.0 {
+                xyz = xyz + (spectrum.0[i] / self.pdf[i]) * cie_xyz(self.lambda[i]);
+            }
+        }
+        let xyz = xyz / NUM_WAVELENGTHS as f32;
+        xyz_to_srgb(xyz) / white_rgb()
+    }
+
+    /// An sRGB colour upsampled to a spectrum, at each wavelength
+    pub fn upsample(&self, rgb: Vec3) -> SampledSpectrum {
+        SampledSpectrum(self.lambda.map(|lambda| rgb_to_spectrum(rgb, lambda)))
+    }
+}
+
+/// Values of a spectrum at each of the `SampledWavelengths`
+#[derive(Copy, Clone, Default)]
+pub(crate) struct SampledSpectrum(pub [f32; NUM_WAVELENGTHS]);
+
+impl SampledSpectrum {
+    pub fn max(&self) -> f32 {
+        self.0.iter().copied().fold(f32::MIN, f32::max)
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+impl Mul<SampledSpectrum> for f32 {
+    type Output = SampledSpectrum;
+
+    fn mul(self, rhs: SampledSpectrum) -> Self::Output {
+        SampledSpectrum(rhs.0.map(|v| self * v))
+    }
+}
+
+impl Div<f32> for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn div(self, rhs: f32) -> Self::Output {
+        SampledSpectrum(self.0.map(|v| v / rhs))
+    }
+}
+
+/// Unidirectional path tracing with a spectrum of light along each path, in
+/// place of red, green and blue
+pub struct SpectralPathTracer;
+
+impl RayIntegrator for SpectralPathTracer {
+    fn radiance<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        rng: &mut R,
+    ) -> Radiance {
+        let mut wavelengths = SampledWavelengths::sample(rng);
+        let mut bounces = [SampledSpectrum::default(); 3];
+        bounces[0] = wavelengths.upsample(BACKGROUND_COLOR);
+
+        let mut ray: Ray = ray.to_owned();
+        let mut beta = SampledSpectrum([1.0; NUM_WAVELENGTHS]);
+        // Medium of the subsurface material the path is inside, if any
+        let mut medium: Option<Medium> = None;
+        let mut first_hit = None;
+
+        for bounce in 0..settings.depth {
+            let hit =
+                match medium {
+                    Some(medium) => medium.random_walk(&mut ray, &scene.objects, rng).map(
+                        |(hit_record, weight)| {
+                            beta = beta * wavelengths.upsample(weight);
+                            hit_record
+                        },
+                    ),
+                    // Start t_range at non-zero value to prevent self-intersection
+                    None => scene.objects.hit(&ray, 0.001..f32::MAX),
+                };
+            let Some(hit_record) = hit else {
+                break;
+            };
+            if bounce == 0 {
+                first_hit = Some(FirstHit::new(&ray, &hit_record));
+            }
+
+            let mut light =
+                beta * wavelengths.upsample(hit_record.material.emit(hit_record.intersection));
+            if let (Some(max), true) = (settings.indirect_clamp, bounce > 0) {
+                let brightest = light.max();
+                if brightest > max {
+                    light = (max / brightest) * light;
+                }
+            }
+            let index = (bounce as usize).min(2);
+            bounces[index] = bounces[index] + light;
+
+            let Some((new_ray, attenuation)) =
+                hit_record
+                    .material
+                    .scatter_spectral(&ray, &hit_record, &mut wavelengths, rng)
+            else {
+                break;
+            };
+            beta = beta * attenuation;
+            medium = medium_after(medium, &hit_record, &ray, &new_ray);
+            ray = new_ray;
+
+            if settings
+                .russian_roulette
+                .is_some_and(|min| bounce + 1 >= min)
+            {
+                // Paths that can carry more light are more likely to survive,
+                // and are weighted up to make up for the ones that don't
+                let survival = beta.max().clamp(0.05, 1.0);
+                if rng.gen::<f32>() >= survival {
+                    break;
+                }
+                beta = beta / survival;
+            }
+        }
+
+        Radiance {
+            emission: wavelengths.to_rgb(bounces[0]),
+            direct: wavelengths.to_rgb(bounces[1]),
+            indirect: wavelengths.to_rgb(bounces[2]),
+            first_hit,
+        }
+    }
+}
+
+/// Bins of Smits' spectra, spanning the visible range
+const SMITS_BINS: usize = 10;
+
+/// Smits' reflectance spectra for white, cyan, magenta, yellow, red, green and
+/// blue, from "An RGB-to-Spectrum Conversion for Reflectances" (1999)
+const SMITS_WHITE: [f32; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f32; SMITS_BINS] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f32; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f32; SMITS_BINS] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; SMITS_BINS] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; SMITS_BINS] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f32; SMITS_BINS] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Value at `lambda` of a smooth spectrum with the colour `rgb`, built from the
+/// white spectrum and one primary and one secondary colour. Lights are
+/// upsampled the same way as reflectances, with the white balance in `to_rgb`
+/// making up for the difference.
+fn rgb_to_spectrum(rgb: Vec3, lambda: f32) -> f32 {
+    let bin = ((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * SMITS_BINS as f32) as usize;
+    let bin = bin.min(SMITS_BINS - 1);
+    let [white, cyan, magenta, yellow, red, green, blue] = [
+        SMITS_WHITE,
+        SMITS_CYAN,
+        SMITS_MAGENTA,
+        SMITS_YELLOW,
+        SMITS_RED,
+        SMITS_GREEN,
+        SMITS_BLUE,
+    ]
+    .map(|spectrum| spectrum[bin]);
+
+    let Vec3(r, g, b) = rgb;
+    if r <= g && r <= b {
+        r * white
+            + if g <= b {
+                (g - r) * cyan + (b - g) * blue
+            } else {
+                (b - r) * cyan + (g - b) * green
+            }
+    } else if g <= r && g <= b {
+        g * white
+            + if r <= b {
+                (r - g) * magenta + (b - r) * blue
+            } else {
+                (b - g) * magenta + (r - b) * red
+            }
+    } else {
+        b * white
+            + if r <= g {
+                (r - b) * yellow + (g - r) * green
+            } else {
+                (g - b) * yellow + (r - g) * red
+            }
+    }
+}
+
+/// The CIE 1931 colour matching functions at `lambda`, using the multi-lobe
+/// fit from Wyman et al., "Simple Analytic Approximations to the CIE XYZ Color
+/// Matching Functions" (2013)
+fn cie_xyz(lambda: f32) -> Vec3 {
+    let lobe = |mean: f32, below: f32, above: f32| {
+        let t = (lambda - mean) / if lambda < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    Vec3(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+fn xyz_to_srgb(xyz: Vec3) -> Vec3 {
+    let Vec3(x, y, z) = xyz;
+    Vec3(
+        3.240_454 * x - 1.537_139 * y - 0.498_531 * z,
+        -0.969_266 * x + 1.876_011 * y + 0.041_556 * z,
+        0.055_643 * x - 0.204_026 * y + 1.057_225 * z,
+    )
+}
+
+/// Linear sRGB of a flat spectrum of 1 over the visible range, which
+/// `to_rgb` divides by so that it comes out white
+fn white_rgb() -> Vec3 {
+    static WHITE: OnceLock<Vec3> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        const STEPS: u32 = 1000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f32;
+        let xyz: Vec3 = (0..STEPS)
+            .map(|i| step * cie_xyz(LAMBDA_MIN + (i as f32 + 0.5) * step))
+            .sum();
+        xyz_to_srgb(xyz)
+    })
+}
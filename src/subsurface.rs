@@ -0,0 +1,109 @@
+//! Subsurface scattering by random walks. Light that enters a subsurface
+//! material scatters around inside it, bouncing off particles in the volume
+//! until it leaves through the surface again or is absorbed.
+
+use rand::Rng;
+
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// Scattering events a walk can take before it's given up on
+const MAX_STEPS: u32 = 256;
+
+/// The volume inside a subsurface material
+#[derive(Copy, Clone)]
+pub(crate) struct Medium {
+    /// Fraction of light that survives each scattering event, per colour
+    pub albedo: Vec3,
+    /// Average distance light travels between scattering events, per colour
+    pub mean_free_path: Vec3,
+}
+
+impl Medium {
+    /// Follows `ray` as it scatters around inside the medium, until it reaches
+    /// the surface. Returns the hit there, leaving `ray` as the last step of the
+    /// walk, along with the weight for the path's throughput. `None` if the
+    /// walk is absorbed or never reaches the surface.
+    pub fn random_walk<'a, R: Rng + ?Sized>(
+        &self,
+        ray: &mut Ray,
+        objects: &'a HittableList,
+        rng: &mut R,
+    ) -> Option<(HitRecord<'a>, Vec3)> {
+        let extinction = self.mean_free_path.map(|mfp| 1.0 / mfp.max(1e-6));
+        let mut weight = Vec3(1.0, 1.0, 1.0);
+
+        for _ in 0..MAX_STEPS {
+            let hit = objects.hit(ray, 0.001..f32::MAX)?;
+            let dir_length = ray.dir.magnitude();
+            let surface_distance = hit.t * dir_length;
+
+            // Each colour has its own extinction, so pick one to sample the
+            // distance with and weight by the average density of all three
+            let channel = rng.gen_range(0..3);
+            let sigma = [extinction.0, extinction.1, extinction.2][channel];
+            let distance = -(1.0 - rng.gen::<f32>()).ln() / sigma;
+
+            if distance >= surface_distance {
+                let transmittance = (-surface_distance * extinction).map(f32::exp);
+                let pdf = average(transmittance);
+                return Some((hit, weight * transmittance / pdf));
+            }
+
+            let transmittance = (-distance * extinction).map(f32::exp);
+            let pdf = average(extinction * transmittance);
+            weight = weight * (self.albedo * extinction * transmittance) / pdf;
+
+            // Paths that carry little light are ended early, and the rest are
+            // weighted up to make up for them
+            let survival = weight.reduce(f32::max).clamp(0.05, 1.0);
+            if rng.gen::<f32>() >= survival {
+                return None;
+            }
+            weight = weight / survival;
+
+            // Particles scatter equally in all directions
+            *ray = Ray {
+                origin: ray.at_param(distance / dir_length),
+                dir: Vec3::random_unit_vector(rng),
+            };
+        }
+
+        None
+    }
+}
+
+/// The medium a path is in after scattering from `ray` to `scattered` at a
+/// hit, having been in `current` before. Passing through the surface of a
+/// subsurface material enters its medium, or leaves it if already inside.
+pub(crate) fn medium_after(
+    current: Option<Medium>,
+    hit_record: &HitRecord,
+    ray: &Ray,
+    scattered: &Ray,
+) -> Option<Medium> {
+    let Material::Subsurface {
+        albedo,
+        mean_free_path,
+        ..
+    } = hit_record.material
+    else {
+        return current;
+    };
+
+    let crossed = ray.dir.dot(hit_record.normal) * scattered.dir.dot(hit_record.normal) > 0.0;
+    match (crossed, current) {
+        (false, _) => current,
+        (true, Some(_)) => None,
+        (true, None) => Some(Medium {
+            albedo: *albedo,
+            mean_free_path: *mean_free_path,
+        }),
+    }
+}
+
+fn average(v: Vec3) -> f32 {
+    (v.0 + v.1 + v.2) / 3.0
+}
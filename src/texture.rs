@@ -1,6 +1,6 @@
 use crate::vec3::Vec3;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum Texture {
     Constant(Vec3),
     Checkered(Vec3, Vec3),
@@ -0,0 +1,216 @@
+//! Interference in a thin coating on a surface. Light reflected from the top
+//! and bottom of the film interferes, strengthening some wavelengths and
+//! cancelling others depending on the film's thickness and the viewing angle,
+//! which gives soap bubbles and oil slicks their colours.
+
+use std::f32::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+
+/// A transparent coating much thinner than the surface it covers
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ThinFilm {
+    /// In nanometres
+    pub thickness: f32,
+    pub ior: f32,
+}
+
+/// What lies beneath the film
+#[derive(Copy, Clone)]
+pub(crate) enum Substrate {
+    /// A transparent material with this refractive index
+    Dielectric(f32),
+    /// A metal that reflects this fraction of light head-on. Treated as an
+    /// ideal conductor, which reflects with a phase shift of half a wavelength.
+    Conductor(f32),
+}
+
+impl ThinFilm {
+    /// Fraction of unpolarised light of wavelength `lambda` (in nanometres)
+    /// reflected by the film and what's beneath it, for light arriving through
+    /// a medium with refractive index `outer_ior` at an angle with cosine
+    /// `cos_i` to the normal. Uses Airy's formula for the multiple reflections
+    /// within the film.
+    pub(crate) fn reflectance(
+        &self,
+        outer_ior: f32,
+        substrate: Substrate,
+        cos_i: f32,
+        lambda: f32,
+    ) -> f32 {
+        let sin2_i = 1.0 - cos_i * cos_i;
+        let cos_film = Complex::sqrt_of(1.0 - sin2_i * (outer_ior / self.ior).powi(2));
+
+        // Change in phase of light crossing the film and back again
+        let phase = Complex::real(4.0 * PI * self.ior * self.thickness / lambda) * cos_film;
+        let shift = Complex::exp_i(phase);
+
+        let polarised = |s_polarised: bool| {
+            let r12 = fresnel(
+                outer_ior,
+                self.ior,
+                Complex::real(cos_i),
+                cos_film,
+                s_polarised,
+            );
+            let r23 = match substrate {
+                Substrate::Dielectric(ior) => {
+                    let cos_t = Complex::sqrt_of(1.0 - sin2_i * (outer_ior / ior).powi(2));
+                    fresnel(self.ior, ior, cos_film, cos_t, s_polarised)
+                }
+                Substrate::Conductor(reflectance) => Complex::real(-reflectance.sqrt()),
+            };
+            let numerator = r12 + r23 * shift;
+            let denominator = Complex::real(1.0) + r12 * r23 * shift;
+            (numerator / denominator).norm_sqr()
+        };
+
+        (0.5 * (polarised(true) + polarised(false))).clamp(0.0, 1.0)
+    }
+}
+
+/// Fresnel amplitude reflection coefficient for light going from `n1` to `n2`,
+/// with the given cosines of the angles to the normal on each side
+fn fresnel(n1: f32, n2: f32, cos1: Complex, cos2: Complex, s_polarised: bool) -> Complex {
+    let (n1, n2) = (Complex::real(n1), Complex::real(n2));
+    if s_polarised {
+        (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2)
+    } else {
+        (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2)
+    }
+}
+
+/// Just enough complex arithmetic for the film's reflection coefficients, which
+/// become complex past total internal reflection
+#[derive(Copy, Clone)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn real(re: f32) -> Self {
+        Self { re, im: 0.0 }
+    }
+
+    /// Square root of a real number, imaginary if it's negative
+    fn sqrt_of(x: f32) -> Self {
+        if x >= 0.0 {
+            Self::real(x.sqrt())
+        } else {
+            Self {
+                re: 0.0,
+                im: (-x).sqrt(),
+            }
+        }
+    }
+
+    /// `e^(i z)`
+    fn exp_i(z: Complex) -> Self {
+        let magnitude = (-z.im).exp();
+        Self {
+            re: magnitude * z.re.cos(),
+            im: magnitude * z.re.sin(),
+        }
+    }
+
+    fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Complex {
+            re: self.re + rhs.re,
+            im: self.im + rhs.im,
+        }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Complex {
+            re: self.re - rhs.re,
+            im: self.im - rhs.im,
+        }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Complex {
+            re: self.re * rhs.re - self.im * rhs.im,
+            im: self.re * rhs.im + self.im * rhs.re,
+        }
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        let denominator = rhs.norm_sqr();
+        Complex {
+            re: (self.re * rhs.re + self.im * rhs.im) / denominator,
+            im: (self.im * rhs.re - self.re * rhs.im) / denominator,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresnel reflectance of unpolarised light at a bare interface
+    fn bare_reflectance(n1: f32, n2: f32, cos_i: f32) -> f32 {
+        let cos_t = (1.0 - (1.0 - cos_i * cos_i) * (n1 / n2).powi(2)).sqrt();
+        let s = (n1 * cos_i - n2 * cos_t) / (n1 * cos_i + n2 * cos_t);
+        let p = (n2 * cos_i - n1 * cos_t) / (n2 * cos_i + n1 * cos_t);
+        0.5 * (s * s + p * p)
+    }
+
+    #[test]
+    fn film_with_no_thickness_reflects_like_the_bare_surface() {
+        let film = ThinFilm {
+            thickness: 0.0,
+            ior: 1.33,
+        };
+        for cos_i in [1.0, 0.8, 0.5, 0.2] {
+            for lambda in [450.0, 550.0, 650.0] {
+                let coated = film.reflectance(1.0, Substrate::Dielectric(1.5), cos_i, lambda);
+                let bare = bare_reflectance(1.0, 1.5, cos_i);
+                assert!((coated - bare).abs() < 1e-5, "{cos_i}: {coated} != {bare}");
+            }
+        }
+    }
+
+    #[test]
+    fn film_matching_its_surroundings_changes_nothing() {
+        let film = ThinFilm {
+            thickness: 300.0,
+            ior: 1.0,
+        };
+        let metal = film.reflectance(1.0, Substrate::Conductor(0.6), 1.0, 550.0);
+        assert!((metal - 0.6).abs() < 1e-5);
+        let glass = film.reflectance(1.0, Substrate::Dielectric(1.5), 0.7, 550.0);
+        assert!((glass - bare_reflectance(1.0, 1.5, 0.7)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn quarter_wave_coating_cancels_reflection() {
+        let ior = 1.5_f32.sqrt();
+        let film = ThinFilm {
+            thickness: 550.0 / (4.0 * ior),
+            ior,
+        };
+        assert!(film.reflectance(1.0, Substrate::Dielectric(1.5), 1.0, 550.0) < 1e-5);
+        // Other colours aren't cancelled completely
+        assert!(film.reflectance(1.0, Substrate::Dielectric(1.5), 1.0, 400.0) > 1e-3);
+    }
+}
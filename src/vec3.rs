@@ -1,7 +1,7 @@
 use rand::Rng;
 use std::ops::{Add, Div, Mul, Neg, Sub};
 
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone, Default, PartialEq)]
 pub struct Vec3(pub f32, pub f32, pub f32);
 
 impl Vec3 {
@@ -25,6 +25,11 @@ impl Vec3 {
         (self * rhs).reduce(Add::add)
     }
 
+    /// Perceived brightness of a linear sRGB colour
+    pub fn luminance(self) -> f32 {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
+    }
+
     pub fn cross(self, rhs: Self) -> Vec3 {
         Vec3(
             self.1 * rhs.2 - self.2 * rhs.1,
@@ -33,17 +38,21 @@ impl Vec3 {
         )
     }
 
-    pub fn random_in_unit_sphere() -> Self {
+    pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Self {
         loop {
-            let v = (2.0 * Self::random()) - 1.0;
+            let v = (2.0 * Self::random(rng)) - 1.0;
             if v.dot(v) < 1.0 {
                 return v;
             }
         }
     }
 
-    pub fn random_in_unit_disc() -> Self {
-        let mut rng = rand::thread_rng();
+    /// A uniformly distributed point on the surface of the unit sphere
+    pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        Self::random_in_unit_sphere(rng).normalize()
+    }
+
+    pub fn random_in_unit_disc<R: Rng + ?Sized>(rng: &mut R) -> Self {
         loop {
             let v = 2.0 * Vec3(rng.gen(), rng.gen(), 0.0) - Vec3(1.0, 1.0, 0.0);
             if v.dot(v) < 1.0 {
@@ -52,8 +61,7 @@ impl Vec3 {
         }
     }
 
-    pub fn random() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Self {
         Vec3(rng.gen(), rng.gen(), rng.gen())
     }
 }
@@ -107,6 +115,14 @@ impl Div<f32> for Vec3 {
     }
 }
 
+impl Div for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, rhs: Self) -> Vec3 {
+        Vec3(self.0 / rhs.0, self.1 / rhs.1, self.2 / rhs.2)
+    }
+}
+
 impl Mul for Vec3 {
     type Output = Vec3;
 